@@ -0,0 +1,70 @@
+use std::rc::Rc;
+use std::path::PathBuf;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+use persist;
+
+/// Operators of the bot, along with who they banned and whether the bot
+/// is in maintenance.
+#[derive(Clone)]
+pub struct Admin {
+  admins: Rc<Vec<i64>>,
+  bans_file: PathBuf,
+  banned: Rc<RefCell<HashSet<i64>>>,
+  maintenance: Rc<Cell<bool>>,
+}
+
+impl Admin {
+  /// Loads the bans saved at `bans_file`, if any.
+  pub fn load(admins: Vec<i64>, bans_file: PathBuf) -> Admin {
+    let banned = persist::load(&bans_file, "bans");
+    Admin {
+      bans_file,
+      admins: Rc::new(admins),
+      banned: Rc::new(RefCell::new(banned)),
+      maintenance: Rc::new(Cell::new(false)),
+    }
+  }
+
+  fn save(&self) {
+    persist::save(&self.bans_file, &*self.banned.borrow(), "bans");
+  }
+
+  pub fn is_admin(&self, telegram_id: i64) -> bool {
+    self.admins.contains(&telegram_id)
+  }
+
+  pub fn is_banned(&self, telegram_id: i64) -> bool {
+    self.banned.borrow().contains(&telegram_id)
+  }
+
+  /// Returns false if the user was already banned. Admins can't be banned.
+  pub fn ban(&self, telegram_id: i64) -> bool {
+    if self.is_admin(telegram_id) {
+      return false;
+    }
+    let inserted = self.banned.borrow_mut().insert(telegram_id);
+    if inserted {
+      self.save();
+    }
+    inserted
+  }
+
+  /// Returns false if the user wasn't banned.
+  pub fn unban(&self, telegram_id: i64) -> bool {
+    let removed = self.banned.borrow_mut().remove(&telegram_id);
+    if removed {
+      self.save();
+    }
+    removed
+  }
+
+  pub fn in_maintenance(&self) -> bool {
+    self.maintenance.get()
+  }
+
+  pub fn set_maintenance(&self, on: bool) {
+    self.maintenance.set(on)
+  }
+}
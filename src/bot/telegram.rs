@@ -3,27 +3,35 @@ use std::time::Duration;
 
 use futures::{future, Async, Future, Poll, Stream};
 
+use chrono::Utc;
+
+use hyper::mime::Mime;
 use hyper::{Method, Request, Uri};
 use hyper::header::{ContentLength, ContentType};
 
 use serde::ser::Serialize;
 use serde_json::{from_slice, to_string};
 
-use types::Client;
+use types::{Client, Keyboard};
 use types::telegram::*;
+use callback::CallbackRegistry;
 use error::{Error, TelegramError};
 
 #[derive(Clone)]
 pub struct Bot {
   client: Client,
   base_url: String,
+  file_url: String,
+  callbacks: CallbackRegistry,
 }
 
 impl Bot {
-  pub fn new(token: &str, client: Client) -> Bot {
+  pub fn new(token: &str, client: Client, callbacks: CallbackRegistry) -> Bot {
     Bot {
       client,
+      callbacks,
       base_url: format!("https://api.telegram.org/bot{}/", token),
+      file_url: format!("https://api.telegram.org/file/bot{}/", token),
     }
   }
 
@@ -40,6 +48,59 @@ impl Bot {
     req.headers_mut().set(ContentLength(json.len() as u64));
     req.set_body(json);
 
+    self.send(req)
+  }
+
+  /// Sends a `multipart/form-data` request, used when uploading files.
+  fn request_multipart(
+    &self,
+    method: &str,
+    fields: Vec<(&str, String)>,
+    file: (&str, String, Vec<u8>),
+  ) -> Box<Future<Item = Response, Error = Error>> {
+    let uri = Uri::from_str(&format!("{}{}", self.base_url, method)).expect("error/build-uri");
+
+    let boundary = format!("sagiri-{}", Utc::now().timestamp_nanos());
+    let mut body = Vec::new();
+    for (name, value) in fields {
+      body.extend_from_slice(
+        format!(
+          "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+          boundary,
+          name,
+          value
+        ).as_bytes(),
+      );
+    }
+    let (name, file_name, content) = file;
+    body.extend_from_slice(
+      format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n",
+        boundary,
+        name,
+        file_name
+      ).as_bytes(),
+    );
+    body.extend_from_slice(&content);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let mut req = Request::new(Method::Post, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str(&format!("multipart/form-data; boundary={}", boundary))
+        .expect("error/build-mime"),
+    ));
+    req.headers_mut().set(ContentLength(body.len() as u64));
+    req.set_body(body);
+
+    self.send(req)
+  }
+
+  fn inline_keyboard(&self, buttons: Option<Keyboard>) -> Option<ReplyMarkup> {
+    buttons.map(|b| ReplyMarkup::InlineKeyboard(self.callbacks.register_keyboard(b)))
+  }
+
+  fn send(&self, req: Request) -> Box<Future<Item = Response, Error = Error>> {
     Box::new(self.client.request(req).from_err::<Error>().and_then(
       |res| {
         res
@@ -62,13 +123,13 @@ impl Bot {
     chat_id: i64,
     text: String,
     parse_mode: Option<ParseMode>,
-    buttons: Option<Vec<Vec<InlineKeyboardButton>>>,
+    buttons: Option<Keyboard>,
   ) -> Box<Future<Item = Message, Error = Error>> {
     let message = Message {
       parse_mode,
       text: Some(text),
       chat_id: Some(chat_id),
-      reply_markup: buttons.map(|b| ReplyMarkup::InlineKeyboard(b)),
+      reply_markup: self.inline_keyboard(buttons),
       ..Default::default()
     };
     Box::new(self.request::<Message>("sendMessage", &message).and_then(
@@ -79,20 +140,164 @@ impl Bot {
     ))
   }
 
+  /// Sends a message asking the user to reply to it.
+  pub fn send_prompt(&self, chat_id: i64, text: String) -> Box<Future<Item = Message, Error = Error>> {
+    let message = Message {
+      text: Some(text),
+      chat_id: Some(chat_id),
+      parse_mode: Some(ParseMode::HTML),
+      reply_markup: Some(ReplyMarkup::ForceReply {
+        force_reply: true,
+        selective: true,
+      }),
+      ..Default::default()
+    };
+    Box::new(self.request::<Message>("sendMessage", &message).and_then(
+      |res| match res {
+        Response::Message { result } => Ok(result),
+        _ => Err(TelegramError::new("Invalid JSON".to_owned())),
+      },
+    ))
+  }
+
+  pub fn send_photo(
+    &self,
+    chat_id: i64,
+    photo: String,
+    caption: Option<String>,
+    parse_mode: Option<ParseMode>,
+    buttons: Option<Keyboard>,
+  ) -> Box<Future<Item = Message, Error = Error>> {
+    let send_photo = SendPhoto {
+      chat_id,
+      photo,
+      caption,
+      parse_mode,
+      reply_markup: self.inline_keyboard(buttons),
+    };
+    Box::new(self.request::<SendPhoto>("sendPhoto", &send_photo).and_then(
+      |res| match res {
+        Response::Message { result } => Ok(result),
+        _ => Err(TelegramError::new("Invalid JSON".to_owned())),
+      },
+    ))
+  }
+
+  pub fn send_document(
+    &self,
+    chat_id: i64,
+    file_name: String,
+    content: Vec<u8>,
+    caption: Option<String>,
+  ) -> Box<Future<Item = Message, Error = Error>> {
+    let mut fields = vec![("chat_id", chat_id.to_string())];
+    if let Some(caption) = caption {
+      fields.push(("caption", caption));
+    }
+    Box::new(
+      self
+        .request_multipart("sendDocument", fields, ("document", file_name, content))
+        .and_then(|res| match res {
+          Response::Message { result } => Ok(result),
+          _ => Err(TelegramError::new("Invalid JSON".to_owned())),
+        }),
+    )
+  }
+
+  pub fn get_file(&self, file_id: String) -> Box<Future<Item = File, Error = Error>> {
+    let get_file = GetFile { file_id };
+    Box::new(self.request::<GetFile>("getFile", &get_file).and_then(
+      |res| match res {
+        Response::File { result } => Ok(result),
+        _ => Err(TelegramError::new("Invalid JSON".to_owned())),
+      },
+    ))
+  }
+
+  pub fn download_file(&self, file_path: &str) -> Box<Future<Item = Vec<u8>, Error = Error>> {
+    let uri = Uri::from_str(&format!("{}{}", self.file_url, file_path)).expect("error/build-uri");
+
+    Box::new(
+      self
+        .client
+        .get(uri)
+        .from_err::<Error>()
+        .and_then(|res| res.body().from_err::<Error>().concat2())
+        .map(|chunks| chunks.to_vec()),
+    )
+  }
+
+  pub fn get_chat_member(
+    &self,
+    chat_id: i64,
+    user_id: i64,
+  ) -> Box<Future<Item = ChatMember, Error = Error>> {
+    let get_chat_member = GetChatMember { chat_id, user_id };
+    Box::new(
+      self
+        .request::<GetChatMember>("getChatMember", &get_chat_member)
+        .and_then(|res| match res {
+          Response::ChatMember { result } => Ok(result),
+          _ => Err(TelegramError::new("Invalid JSON".to_owned())),
+        }),
+    )
+  }
+
+  pub fn delete_message(
+    &self,
+    chat_id: i64,
+    message_id: i64,
+  ) -> Box<Future<Item = bool, Error = Error>> {
+    let delete_message = DeleteMessage { chat_id, message_id };
+    Box::new(
+      self
+        .request::<DeleteMessage>("deleteMessage", &delete_message)
+        .and_then(|res| match res {
+          Response::Bool { result } => Ok(result),
+          _ => Err(TelegramError::new("Invalid JSON".to_owned())),
+        }),
+    )
+  }
+
+  /// Replaces the content of a message with text. Photo messages can't be
+  /// turned into text ones, so they are deleted and sent again instead.
+  pub fn replace_message(
+    &self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    text: String,
+    parse_mode: Option<ParseMode>,
+    buttons: Option<Keyboard>,
+  ) -> Box<Future<Item = Message, Error = Error>> {
+    if has_photo {
+      let bot = self.clone();
+      Box::new(
+        self
+          .delete_message(chat_id, msg_id)
+          .and_then(move |_| bot.send_message(chat_id, text, parse_mode, buttons)),
+      )
+    } else {
+      self
+        .clone()
+        .edit_inline_keyboard(msg_id, chat_id, text, parse_mode, buttons)
+    }
+  }
+
   pub fn edit_inline_keyboard(
     self,
     msg_id: i64,
     chat_id: i64,
     text: String,
     parse_mode: Option<ParseMode>,
-    buttons: Option<Vec<Vec<InlineKeyboardButton>>>,
+    buttons: Option<Keyboard>,
   ) -> Box<Future<Item = Message, Error = Error>> {
     let message = Message {
       parse_mode,
       text: Some(text),
       chat_id: Some(chat_id),
       message_id: Some(msg_id),
-      reply_markup: buttons.map(|b| ReplyMarkup::InlineKeyboard(b)),
+      reply_markup: self.inline_keyboard(buttons),
       ..Default::default()
     };
     Box::new(
@@ -0,0 +1,164 @@
+use std::rc::Rc;
+use std::path::PathBuf;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+
+use futures::Stream;
+
+use tokio_core::reactor::{Handle, Interval};
+
+use persist;
+use types::{Keyboard, QueryCommand};
+use types::telegram::InlineKeyboardButton;
+
+/// How long a button keeps working after it was sent.
+pub const CALLBACK_TTL_HOURS: i64 = 48;
+
+/// Seconds between two saves of new buttons.
+const SAVE_INTERVAL: u64 = 30;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Callback {
+  pub command: QueryCommand,
+  #[serde(default)] pub owner: Option<i64>,
+  expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Registry {
+  next_key: u64,
+  callbacks: HashMap<String, Callback>,
+}
+
+/// Maps the short keys sent to Telegram as `callback_data` to the commands
+/// they stand for, since callback data is capped at 64 bytes.
+#[derive(Clone)]
+pub struct CallbackRegistry {
+  path: PathBuf,
+  registry: Rc<RefCell<Registry>>,
+  /// Whether there are buttons that aren't saved yet.
+  dirty: Rc<Cell<bool>>,
+}
+
+fn encode_key(mut n: u64) -> String {
+  const DIGITS: &'static [u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+  let mut key = Vec::new();
+  loop {
+    key.push(DIGITS[(n % 36) as usize]);
+    n /= 36;
+    if n == 0 {
+      break;
+    }
+  }
+  key.reverse();
+  String::from_utf8(key).expect("error/encode-key")
+}
+
+impl CallbackRegistry {
+  /// Loads the registry saved at `path`, or starts an empty one.
+  pub fn load(path: PathBuf) -> CallbackRegistry {
+    let registry = persist::load(&path, "callbacks");
+    CallbackRegistry {
+      path,
+      registry: Rc::new(RefCell::new(registry)),
+      dirty: Rc::new(Cell::new(false)),
+    }
+  }
+
+  fn save(&self) {
+    self.dirty.set(false);
+    persist::save(&self.path, &*self.registry.borrow(), "callbacks");
+  }
+
+  /// Saves new buttons every few seconds rather than on every keyboard, so
+  /// a page of buttons is one write.
+  pub fn start(&self, handle: &Handle) {
+    let registry = self.clone();
+    handle.spawn(
+      Interval::new(StdDuration::from_secs(SAVE_INTERVAL), handle)
+        .expect("error/init-interval")
+        .map_err(|e| error!("callback timer: {:?}", e))
+        .for_each(move |_| {
+          if registry.dirty.get() {
+            registry.save();
+          }
+          Ok(())
+        }),
+    );
+  }
+
+  /// Registers every button of the keyboard, returning the keyboard that is
+  /// actually sent to Telegram.
+  pub fn register_keyboard(&self, keyboard: Keyboard) -> Vec<Vec<InlineKeyboardButton>> {
+    let expires_at = Utc::now() + Duration::hours(CALLBACK_TTL_HOURS);
+    let buttons = {
+      let mut registry = self.registry.borrow_mut();
+      keyboard
+        .into_iter()
+        .map(|row| {
+          row
+            .into_iter()
+            .map(|button| {
+              let key = encode_key(registry.next_key);
+              registry.next_key += 1;
+              registry.callbacks.insert(
+                key.clone(),
+                Callback {
+                  expires_at,
+                  command: button.command,
+                  owner: button.owner,
+                },
+              );
+              InlineKeyboardButton::with_callback_data(button.text, key)
+            })
+            .collect()
+        })
+        .collect()
+    };
+    self.dirty.set(true);
+    buttons
+  }
+
+  /// Decodes the callback data of a pressed button. Returns `None` for
+  /// unknown or expired keys.
+  pub fn parse_query(&self, key: &str) -> Option<Callback> {
+    let registry = self.registry.borrow();
+    registry
+      .callbacks
+      .get(key)
+      .and_then(|callback| if callback.expires_at > Utc::now() {
+        Some(callback.clone())
+      } else {
+        None
+      })
+  }
+
+  /// Drops the expired buttons, saving what's left. Returns how many there
+  /// were.
+  pub fn expire(&self) -> usize {
+    let expired = {
+      let mut registry = self.registry.borrow_mut();
+      let count = registry.callbacks.len();
+      let now = Utc::now();
+      registry.callbacks.retain(|_, callback| callback.expires_at > now);
+      count - registry.callbacks.len()
+    };
+    if expired > 0 || self.dirty.get() {
+      self.save();
+    }
+    expired
+  }
+
+  /// Drops every button bound to the user.
+  pub fn forget(&self, owner: i64) {
+    self
+      .registry
+      .borrow_mut()
+      .callbacks
+      .retain(|_, callback| callback.owner != Some(owner));
+    self.save();
+  }
+}
@@ -0,0 +1,126 @@
+use std::env;
+use std::str::FromStr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crypto::{from_hex, KEY_LEN};
+
+/// Seconds between two refreshes of the user registry, unless
+/// `REFRESH_INTERVAL` says otherwise.
+const DEFAULT_REFRESH_INTERVAL: u64 = 300;
+const DEFAULT_MAX_JOBS: usize = 2;
+
+/// Seconds between two checks for new episodes.
+const DEFAULT_NOTIFY_INTERVAL: u64 = 1800;
+
+/// Runtime settings, read from environment variables.
+pub struct Config {
+  /// Telegram ids allowed to run operator commands.
+  pub admins: Vec<i64>,
+  pub bans_file: PathBuf,
+  /// Local file to load users from, instead of firebase.
+  pub users_file: Option<PathBuf>,
+  /// Kitsu accounts picked with `/use`.
+  pub active_file: PathBuf,
+  pub callbacks_file: PathBuf,
+  pub settings_file: PathBuf,
+  pub shared_file: PathBuf,
+  pub airing_file: PathBuf,
+  pub jobs_file: PathBuf,
+  /// Message templates overriding the built-in ones.
+  pub templates_file: Option<PathBuf>,
+  /// How many background jobs may run at once.
+  pub max_jobs: usize,
+  /// `None` if the users should only be loaded once, at startup.
+  pub refresh_interval: Option<Duration>,
+  /// `None` if new episodes shouldn't be checked for.
+  pub notify_interval: Option<Duration>,
+  /// Key encrypting the Kitsu tokens.
+  pub token_key: Option<Vec<u8>>,
+  /// Previous keys, still accepted when decrypting until `/rotatekey`.
+  pub old_token_keys: Vec<Vec<u8>>,
+}
+
+fn parse_key(name: &str, hex: &str) -> Vec<u8> {
+  match from_hex(hex.trim()) {
+    Some(ref key) if key.len() == KEY_LEN => key.clone(),
+    _ => panic!("{} must be {} bytes written in hex", name, KEY_LEN),
+  }
+}
+
+/// Reads a number of seconds, where `0` turns the job off.
+fn parse_interval(name: &str, default: u64) -> Option<Duration> {
+  let secs = match env::var(name) {
+    Ok(secs) => u64::from_str(&secs).unwrap_or_else(|_| {
+      warn!("invalid {}: {:?}, using the default", name, secs);
+      default
+    }),
+    Err(_) => default,
+  };
+  if secs > 0 {
+    Some(Duration::from_secs(secs))
+  } else {
+    None
+  }
+}
+
+impl Config {
+  pub fn from_env() -> Config {
+    let refresh_interval = parse_interval("REFRESH_INTERVAL", DEFAULT_REFRESH_INTERVAL);
+    let notify_interval = parse_interval("NOTIFY_INTERVAL", DEFAULT_NOTIFY_INTERVAL);
+    let admins = env::var("ADMINS")
+      .unwrap_or_default()
+      .split(',')
+      .map(|id| id.trim())
+      .filter(|id| !id.is_empty())
+      .filter_map(|id| match i64::from_str(id) {
+        Ok(id) => Some(id),
+        Err(_) => {
+          warn!("invalid telegram id in ADMINS: {:?}", id);
+          None
+        }
+      })
+      .collect();
+    Config {
+      admins,
+      token_key: env::var("TOKEN_KEY")
+        .ok()
+        .map(|key| parse_key("TOKEN_KEY", &key)),
+      old_token_keys: env::var("OLD_TOKEN_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|key| !key.trim().is_empty())
+        .map(|key| parse_key("OLD_TOKEN_KEYS", key))
+        .collect(),
+      bans_file: env::var("BANS_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("bans.json")),
+      users_file: env::var("USERS_FILE").ok().map(PathBuf::from),
+      active_file: env::var("ACTIVE_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("active.json")),
+      callbacks_file: env::var("CALLBACKS_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("callbacks.json")),
+      settings_file: env::var("SETTINGS_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("settings.json")),
+      shared_file: env::var("SHARED_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("shared.json")),
+      airing_file: env::var("AIRING_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("airing.json")),
+      jobs_file: env::var("JOBS_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("jobs.json")),
+      templates_file: env::var("TEMPLATES_FILE").ok().map(PathBuf::from),
+      max_jobs: env::var("MAX_JOBS")
+        .ok()
+        .and_then(|jobs| usize::from_str(&jobs).ok())
+        .unwrap_or(DEFAULT_MAX_JOBS),
+      refresh_interval,
+      notify_interval,
+    }
+  }
+}
@@ -0,0 +1,177 @@
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Marks tokens that are already encrypted.
+const PREFIX: &'static str = "enc:";
+
+pub const KEY_LEN: usize = 32;
+
+/// Encrypts Kitsu tokens at rest. The first key encrypts; the others are
+/// only tried when decrypting, so tokens can be moved to a new key.
+pub struct Cipher {
+  keys: Vec<Vec<u8>>,
+  rng: SystemRandom,
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  let digits = hex
+    .chars()
+    .map(|c| c.to_digit(16).map(|d| d as u8))
+    .collect::<Option<Vec<u8>>>();
+  match digits {
+    Some(ref digits) if digits.len() % 2 == 0 => {
+      Some(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+    }
+    _ => None,
+  }
+}
+
+impl Cipher {
+  pub fn new(key: Vec<u8>, old_keys: Vec<Vec<u8>>) -> Cipher {
+    let mut keys = vec![key];
+    keys.extend(old_keys);
+    Cipher {
+      keys,
+      rng: SystemRandom::new(),
+    }
+  }
+
+  /// Creates a cipher with a random key, which is lost on exit.
+  pub fn ephemeral() -> Cipher {
+    let rng = SystemRandom::new();
+    let mut key = vec![0; KEY_LEN];
+    rng.fill(&mut key).expect("error/random-key");
+    Cipher {
+      rng,
+      keys: vec![key],
+    }
+  }
+
+  pub fn is_sealed(token: &str) -> bool {
+    token.starts_with(PREFIX)
+  }
+
+  fn key(key: &[u8]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("error/token-key"))
+  }
+
+  pub fn seal(&self, token: &str) -> String {
+    let mut nonce = [0; NONCE_LEN];
+    self.rng.fill(&mut nonce).expect("error/random-nonce");
+
+    let mut in_out = token.as_bytes().to_vec();
+    Cipher::key(&self.keys[0])
+      .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut in_out)
+      .expect("error/seal-token");
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(in_out);
+    format!("{}{}", PREFIX, to_hex(&sealed))
+  }
+
+  /// Decrypts a sealed token with whichever key it was sealed with.
+  pub fn open(&self, sealed: &str) -> Option<String> {
+    if !Cipher::is_sealed(sealed) {
+      return None;
+    }
+    let data = from_hex(&sealed[PREFIX.len()..])?;
+    if data.len() < NONCE_LEN + CHACHA20_POLY1305.tag_len() {
+      return None;
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    for key in &self.keys {
+      let nonce = match Nonce::try_assume_unique_for_key(nonce) {
+        Ok(nonce) => nonce,
+        Err(_) => return None,
+      };
+      let mut in_out = ciphertext.to_vec();
+      if let Ok(token) = Cipher::key(key).open_in_place(nonce, Aad::empty(), &mut in_out) {
+        return String::from_utf8(token.to_vec()).ok();
+      }
+    }
+    None
+  }
+
+  /// Seals a plaintext token, or seals a sealed one again with the current
+  /// key. Returns `None` if the token can't be decrypted by any key.
+  pub fn reseal(&self, token: &str) -> Option<String> {
+    if Cipher::is_sealed(token) {
+      self.open(token).map(|token| self.seal(&token))
+    } else {
+      Some(self.seal(token))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Cipher, KEY_LEN};
+
+  fn cipher(key: u8, old_keys: &[u8]) -> Cipher {
+    Cipher::new(
+      vec![key; KEY_LEN],
+      old_keys.iter().map(|&key| vec![key; KEY_LEN]).collect(),
+    )
+  }
+
+  #[test]
+  fn seal_and_open() {
+    let cipher = cipher(1, &[]);
+    let sealed = cipher.seal("kitsu-token");
+    assert!(Cipher::is_sealed(&sealed));
+    assert!(!sealed.contains("kitsu-token"));
+    assert_eq!(cipher.open(&sealed), Some(String::from("kitsu-token")));
+  }
+
+  #[test]
+  fn seals_differently_every_time() {
+    let cipher = cipher(1, &[]);
+    assert_ne!(cipher.seal("kitsu-token"), cipher.seal("kitsu-token"));
+  }
+
+  #[test]
+  fn does_not_open_with_another_key() {
+    let sealed = cipher(1, &[]).seal("kitsu-token");
+    assert_eq!(cipher(2, &[]).open(&sealed), None);
+  }
+
+  #[test]
+  fn does_not_open_garbage() {
+    let cipher = cipher(1, &[]);
+    assert_eq!(cipher.open("kitsu-token"), None);
+    assert_eq!(cipher.open("enc:zz"), None);
+    assert_eq!(cipher.open("enc:00"), None);
+    let mut sealed = cipher.seal("kitsu-token");
+    let last = if sealed.ends_with('0') { "1" } else { "0" };
+    sealed.pop();
+    sealed.push_str(last);
+    assert_eq!(cipher.open(&sealed), None);
+  }
+
+  #[test]
+  fn reseal_moves_tokens_to_the_new_key() {
+    let sealed = cipher(1, &[]).seal("kitsu-token");
+    let rotated = cipher(2, &[1]);
+    assert_eq!(rotated.open(&sealed), Some(String::from("kitsu-token")));
+    let resealed = rotated.reseal(&sealed).unwrap();
+    assert_eq!(cipher(2, &[]).open(&resealed), Some(String::from("kitsu-token")));
+    assert_eq!(cipher(1, &[]).open(&resealed), None);
+  }
+
+  #[test]
+  fn reseal_seals_plaintext() {
+    let cipher = cipher(1, &[]);
+    let sealed = cipher.reseal("kitsu-token").unwrap();
+    assert_eq!(cipher.open(&sealed), Some(String::from("kitsu-token")));
+  }
+
+  #[test]
+  fn reseal_gives_up_on_unknown_keys() {
+    let sealed = cipher(1, &[]).seal("kitsu-token");
+    assert_eq!(cipher(2, &[]).reseal(&sealed), None);
+  }
+}
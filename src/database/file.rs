@@ -0,0 +1,65 @@
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use futures::{done, Future};
+
+use serde_json::{from_reader, to_writer_pretty};
+
+use super::UserStore;
+use error::Error;
+use types::User;
+
+/// Users kept in a local JSON file, a list of objects with `kitsu_id`,
+/// `telegram_id` and `kitsu_token`. The file is read again on every load,
+/// so it can be edited while sagiri is running. Plaintext tokens added by
+/// hand are encrypted the next time it's loaded.
+pub struct FileStore {
+  path: PathBuf,
+}
+
+impl FileStore {
+  pub fn new(path: PathBuf) -> FileStore {
+    FileStore { path }
+  }
+
+  fn read(&self) -> Result<Vec<User>, Error> {
+    match File::open(&self.path) {
+      Ok(file) => Ok(from_reader(file)?),
+      Err(ref e) if e.kind() == ErrorKind::NotFound => {
+        info!("{:?} doesn't exist yet, no users loaded", self.path);
+        Ok(Vec::new())
+      }
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  fn write(&self, users: &[User]) -> Result<(), Error> {
+    // write next to the file first, so a crash can't leave it half written
+    let tmp = self.path.with_extension("tmp");
+    to_writer_pretty(File::create(&tmp)?, users)?;
+    fs::rename(&tmp, &self.path)?;
+    Ok(())
+  }
+}
+
+impl UserStore for FileStore {
+  fn load(&self) -> Box<Future<Item = Vec<User>, Error = Error>> {
+    Box::new(done(self.read()))
+  }
+
+  fn save(&self, users: &[User]) -> Option<Box<Future<Item = (), Error = Error>>> {
+    Some(Box::new(done(self.write(users))))
+  }
+
+  fn remove(&self, telegram_id: i64) -> Option<Box<Future<Item = (), Error = Error>>> {
+    let res = self.read().and_then(|users| {
+      let users: Vec<User> = users
+        .into_iter()
+        .filter(|user| user.telegram_id != telegram_id)
+        .collect();
+      self.write(&users)
+    });
+    Some(Box::new(done(res)))
+  }
+}
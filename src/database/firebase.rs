@@ -0,0 +1,61 @@
+use std::str::FromStr;
+
+use futures::{future, Future, Stream};
+
+use hyper::{Method, Request, Uri};
+use hyper::header::Authorization;
+
+use serde_json::from_slice;
+
+use super::UserStore;
+use error::{DatabaseError, Error};
+use types::{Client, DatabaseResponse as Response, User};
+
+/// Users kept behind the sagiri Firebase function.
+pub struct FirebaseStore {
+  uri: Uri,
+  token: String,
+  client: Client,
+}
+
+impl FirebaseStore {
+  pub fn new(token: String, client: Client) -> FirebaseStore {
+    FirebaseStore {
+      token,
+      client,
+      uri: Uri::from_str("https://sagiri-izumi.firebaseapp.com/api/kitsu/user").unwrap(),
+    }
+  }
+}
+
+impl UserStore for FirebaseStore {
+  fn load(&self) -> Box<Future<Item = Vec<User>, Error = Error>> {
+    let mut req = Request::new(Method::Get, self.uri.clone());
+    req.headers_mut().set(Authorization(self.token.clone()));
+
+    Box::new(self.client.request(req).from_err::<Error>().and_then(
+      |res| {
+        res
+          .body()
+          .from_err::<Error>()
+          .concat2()
+          .and_then(|chunks| {
+            future::result::<Response, Error>(from_slice(&chunks).map_err(|e| e.into()))
+          })
+          .and_then(|res| match res {
+            Response::Ok { data } => Ok(data),
+            Response::Error { error } => Err(Error::Database(DatabaseError { description: error })),
+          })
+      },
+    ))
+  }
+
+  fn save(&self, _users: &[User]) -> Option<Box<Future<Item = (), Error = Error>>> {
+    None
+  }
+
+  fn remove(&self, _telegram_id: i64) -> Option<Box<Future<Item = (), Error = Error>>> {
+    // read-only, like `save`
+    None
+  }
+}
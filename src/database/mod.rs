@@ -0,0 +1,189 @@
+mod file;
+mod firebase;
+mod registry;
+
+pub use self::file::FileStore;
+pub use self::firebase::FirebaseStore;
+
+use std::rc::Rc;
+use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use futures::{done, Future};
+
+use crypto::Cipher;
+use error::Error;
+use persist;
+use types::{Secret, User};
+use self::registry::Registry;
+
+/// Where the links between Telegram users and Kitsu accounts are kept.
+pub trait UserStore {
+  fn load(&self) -> Box<Future<Item = Vec<User>, Error = Error>>;
+
+  /// Replaces the stored users. Returns `None` if the store is read-only.
+  fn save(&self, users: &[User]) -> Option<Box<Future<Item = (), Error = Error>>>;
+
+  /// Deletes every account linked to the Telegram user. Returns `None` if
+  /// the store can't delete users.
+  fn remove(&self, telegram_id: i64) -> Option<Box<Future<Item = (), Error = Error>>>;
+}
+
+#[derive(Clone)]
+pub struct Database {
+  store: Rc<UserStore>,
+  cipher: Rc<Cipher>,
+  // tokens are kept encrypted, and only decrypted by `get_token`
+  registry: Rc<RefCell<Rc<Registry>>>,
+  // kitsu account picked with `/use`, keyed by telegram id
+  active: Rc<RefCell<HashMap<i64, i64>>>,
+  active_path: PathBuf,
+}
+
+impl Database {
+  /// The accounts picked with `/use` are loaded from `active_path`.
+  pub fn new(store: Rc<UserStore>, cipher: Rc<Cipher>, active_path: PathBuf) -> Database {
+    let active = persist::load(&active_path, "active accounts");
+    Database {
+      store,
+      cipher,
+      active_path,
+      registry: Rc::new(RefCell::new(Rc::new(Registry::default()))),
+      active: Rc::new(RefCell::new(active)),
+    }
+  }
+
+  fn save_active(&self) {
+    persist::save(&self.active_path, &*self.active.borrow(), "active accounts");
+  }
+
+  pub fn fetch(&mut self) -> Box<Future<Item = Vec<User>, Error = Error>> {
+    let registry = self.registry.clone();
+    let store = self.store.clone();
+    let cipher = self.cipher.clone();
+    Box::new(self.store.load().and_then(move |mut data| {
+      let mut plaintext = 0;
+      for user in &mut data {
+        if !Cipher::is_sealed(&user.kitsu_token.0) {
+          user.kitsu_token = Secret(cipher.seal(&user.kitsu_token.0));
+          plaintext += 1;
+        }
+      }
+      *registry.borrow_mut() = Rc::new(Registry::new(data.clone()));
+      // don't leave plaintext tokens in a store we can write to
+      let save = if plaintext > 0 { store.save(&data) } else { None };
+      match save {
+        Some(save) => Box::new(save.then(move |res| {
+          match res {
+            Ok(_) => info!("encrypted {} plaintext token(s) in the store", plaintext),
+            Err(e) => error!("failed to save encrypted tokens: {:?}", e),
+          }
+          Ok(data)
+        })) as Box<Future<Item = Vec<User>, Error = Error>>,
+        None => Box::new(done(Ok(data))),
+      }
+    }))
+  }
+
+  /// Encrypts every token again with the current key. Returns how many
+  /// tokens were re-encrypted, how many couldn't be decrypted, and whether
+  /// the store was updated.
+  pub fn rotate(&self) -> Box<Future<Item = (usize, usize, bool), Error = Error>> {
+    let (mut rotated, mut failed) = (0, 0);
+    let mut data = self.registry().users().to_vec();
+    for user in &mut data {
+      match self.cipher.reseal(&user.kitsu_token.0) {
+        Some(token) => {
+          user.kitsu_token = Secret(token);
+          rotated += 1;
+        }
+        None => failed += 1,
+      }
+    }
+    *self.registry.borrow_mut() = Rc::new(Registry::new(data.clone()));
+    match self.store.save(&data) {
+      Some(save) => Box::new(save.map(move |_| (rotated, failed, true)))
+        as Box<Future<Item = (usize, usize, bool), Error = Error>>,
+      None => Box::new(done(Ok((rotated, failed, false)))),
+    }
+  }
+
+  /// Deletes the Telegram user and every account linked to it from the
+  /// store, and only then forgets them here. Returns `false` if the store
+  /// can't delete users; they're still forgotten here, but come back with
+  /// the next refresh.
+  pub fn remove(&self, telegram_id: i64) -> Box<Future<Item = bool, Error = Error>> {
+    let delete = match self.store.remove(telegram_id) {
+      Some(delete) => delete,
+      None => {
+        self.forget(telegram_id);
+        return Box::new(done(Ok(false)));
+      }
+    };
+    let db = self.clone();
+    Box::new(delete.map(move |_| {
+      db.forget(telegram_id);
+      true
+    }))
+  }
+
+  fn forget(&self, telegram_id: i64) {
+    let users = self
+      .registry()
+      .users()
+      .iter()
+      .filter(|user| user.telegram_id != telegram_id)
+      .cloned()
+      .collect();
+    *self.registry.borrow_mut() = Rc::new(Registry::new(users));
+    if self.active.borrow_mut().remove(&telegram_id).is_some() {
+      self.save_active();
+    }
+  }
+
+  /// The current registry. Lookups through it all see the same users,
+  /// even if a refresh happens in the meantime.
+  fn registry(&self) -> Rc<Registry> {
+    self.registry.borrow().clone()
+  }
+
+  pub fn users(&self) -> Vec<User> {
+    self.registry().users().to_vec()
+  }
+
+  /// Returns the active account of the user, or the first linked one.
+  pub fn get_kitsu_id(&mut self, telegram_id: i64) -> Option<i64> {
+    let kitsu_ids = self.get_kitsu_ids(telegram_id);
+    match self.active.borrow().get(&telegram_id) {
+      Some(kitsu_id) if kitsu_ids.contains(kitsu_id) => Some(*kitsu_id),
+      _ => kitsu_ids.first().cloned(),
+    }
+  }
+
+  pub fn get_kitsu_ids(&self, telegram_id: i64) -> Vec<i64> {
+    self.registry().kitsu_ids(telegram_id).to_vec()
+  }
+
+  /// Makes `kitsu_id` the active account, if it's linked to the user.
+  pub fn set_active(&self, telegram_id: i64, kitsu_id: i64) -> bool {
+    if self.get_kitsu_ids(telegram_id).contains(&kitsu_id) {
+      self.active.borrow_mut().insert(telegram_id, kitsu_id);
+      self.save_active();
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn get_token(&self, telegram_id: i64, kitsu_id: i64) -> Option<String> {
+    let registry = self.registry();
+    registry.token(telegram_id, kitsu_id).and_then(|sealed| {
+      let token = self.cipher.open(&sealed.0);
+      if token.is_none() {
+        error!("can't decrypt the token of {}/{}", telegram_id, kitsu_id);
+      }
+      token
+    })
+  }
+}
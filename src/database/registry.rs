@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+
+use types::{Secret, User};
+
+/// Users indexed by Telegram id and by Telegram and Kitsu ids together.
+/// It's never changed in place: a refresh builds a new one and swaps it in.
+#[derive(Default)]
+pub struct Registry {
+  users: Vec<User>,
+  // kitsu ids linked to each telegram user, in the order they were linked
+  accounts: HashMap<i64, Vec<i64>>,
+  tokens: HashMap<(i64, i64), Secret>,
+}
+
+impl Registry {
+  pub fn new(users: Vec<User>) -> Registry {
+    let mut accounts: HashMap<i64, Vec<i64>> = HashMap::new();
+    let mut tokens = HashMap::new();
+    for user in &users {
+      let kitsu_ids = accounts.entry(user.telegram_id).or_default();
+      if !kitsu_ids.contains(&user.kitsu_id) {
+        kitsu_ids.push(user.kitsu_id);
+      }
+      tokens.insert((user.telegram_id, user.kitsu_id), user.kitsu_token.clone());
+    }
+    Registry {
+      users,
+      accounts,
+      tokens,
+    }
+  }
+
+  pub fn users(&self) -> &[User] {
+    &self.users
+  }
+
+  pub fn kitsu_ids(&self, telegram_id: i64) -> &[i64] {
+    self
+      .accounts
+      .get(&telegram_id)
+      .map(|kitsu_ids| kitsu_ids.as_slice())
+      .unwrap_or(&[])
+  }
+
+  pub fn token(&self, telegram_id: i64, kitsu_id: i64) -> Option<&Secret> {
+    self.tokens.get(&(telegram_id, kitsu_id))
+  }
+}
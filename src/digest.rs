@@ -0,0 +1,269 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use futures::{done, stream, Future, Stream};
+
+use admin::Admin;
+use bot::telegram::Bot;
+use kitsu::Api;
+use error::Error;
+use notify::NotifyStore;
+use settings::{Settings, SettingsStore};
+use database::Database;
+use types::{bind_keyboard, Button, Client, QueryCommand};
+use types::kitsu::{Anime, AnimeStatus, Entry, LibraryEvent};
+use types::telegram::ParseMode;
+use utils::parse_digest;
+
+/// Seconds between two looks for digests that are due.
+pub const CHECK_INTERVAL: u64 = 60;
+
+/// Shows that weren't updated for this many days are listed as stale.
+const STALE_DAYS: i64 = 14;
+
+/// What a digest tells the user about.
+pub struct Digest {
+  /// Titles with the numbers of their unwatched, newly aired episodes.
+  pub new_episodes: Vec<(String, Vec<i64>)>,
+  /// Titles with how long ago they were last updated.
+  pub stale: Vec<(String, Duration)>,
+  pub watched_episodes: i64,
+  pub watched_shows: usize,
+}
+
+impl Digest {
+  fn new(
+    settings: &Settings,
+    since: DateTime<Utc>,
+    shows: Vec<(Entry, Anime)>,
+    events: Vec<LibraryEvent>,
+    aired: HashMap<String, Vec<(i64, NaiveDate)>>,
+  ) -> Digest {
+    let now = Utc::now();
+    let preference = settings.title_language.clone().unwrap_or_default();
+    let since = since.with_timezone(&settings.zone()).naive_local().date();
+    let mut new_episodes = Vec::new();
+    let mut stale = Vec::new();
+    for (entry, anime) in shows {
+      let (title, attr) = match (anime.attributes, entry.attributes) {
+        (Some(anime_attr), Some(attr)) => (anime_attr.title(&preference).to_owned(), attr),
+        _ => continue,
+      };
+      let progress = attr.progress.unwrap_or(0);
+      let mut episodes: Vec<i64> = aired
+        .get(&anime.id)
+        .map_or(&[][..], |episodes| &episodes[..])
+        .iter()
+        .filter(|&&(number, date)| number > progress && date > since)
+        .map(|&(number, _)| number)
+        .collect();
+      episodes.sort();
+      if let Some(updated_at) = attr.updated_at {
+        let idle = now.signed_duration_since(updated_at);
+        if idle > Duration::days(STALE_DAYS) {
+          stale.push((title.clone(), idle));
+        }
+      }
+      if !episodes.is_empty() {
+        new_episodes.push((title, episodes));
+      }
+    }
+    stale.sort_by_key(|&(_, idle)| Reverse(idle));
+
+    let mut watched_episodes = 0;
+    let mut watched_shows = HashSet::new();
+    for event in &events {
+      if let Some((before, Some(after))) = event.attributes.changed_data.progress {
+        let watched = after - before.unwrap_or(0);
+        if watched > 0 {
+          watched_episodes += watched;
+          if let Some(anime_id) = event.anime_id() {
+            watched_shows.insert(anime_id.to_owned());
+          }
+        }
+      }
+    }
+
+    Digest {
+      new_episodes,
+      stale,
+      watched_episodes,
+      watched_shows: watched_shows.len(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.new_episodes.is_empty() && self.stale.is_empty() && self.watched_episodes == 0
+  }
+}
+
+/// Sends daily and weekly digests at the local time users picked.
+#[derive(Clone)]
+pub struct DigestSender {
+  api: Api,
+  bot: Bot,
+  db: Database,
+  admin: Admin,
+  settings: SettingsStore,
+  store: NotifyStore,
+}
+
+impl DigestSender {
+  pub fn new(
+    bot: Bot,
+    client: Client,
+    db: Database,
+    admin: Admin,
+    settings: SettingsStore,
+    store: NotifyStore,
+  ) -> DigestSender {
+    DigestSender {
+      bot,
+      db,
+      admin,
+      settings,
+      store,
+      api: Api::new(client),
+    }
+  }
+
+  /// Sends the digests that are due. Returns how many were sent.
+  pub fn run(&self) -> Box<Future<Item = usize, Error = Error>> {
+    if self.admin.in_maintenance() {
+      return Box::new(done(Ok(0)));
+    }
+    let now = Utc::now();
+    let mut db = self.db.clone();
+    let mut seen = HashSet::new();
+    let mut due = Vec::new();
+    for user in self.db.users() {
+      let telegram_id = user.telegram_id;
+      let settings = self.settings.get(telegram_id);
+      if !seen.insert(telegram_id) || !settings.notify_digest || self.admin.is_banned(telegram_id)
+      {
+        continue;
+      }
+      let due_at = settings.digest_due(&now);
+      let last = self.store.last_digest(telegram_id);
+      let is_due = match last {
+        Some(last) => last < due_at,
+        // just subscribed: only send if we aren't late by much
+        None => now.signed_duration_since(due_at) < Duration::hours(1),
+      };
+      if !is_due {
+        continue;
+      }
+      if let Some(kitsu_id) = db.get_kitsu_id(telegram_id) {
+        let since = last.unwrap_or(due_at - settings.digest_frequency.period());
+        // marked as sent up front, so a failing digest isn't retried every minute
+        self.store.set_last_digest(telegram_id, now);
+        due.push((telegram_id, kitsu_id, settings, since));
+      }
+    }
+
+    let sender = self.clone();
+    Box::new(
+      stream::iter_ok(due)
+        .and_then(move |(telegram_id, kitsu_id, settings, since)| {
+          sender
+            .send(telegram_id, kitsu_id, settings, since)
+            .then(move |res| match res {
+              Ok(sent) => Ok(sent),
+              Err(e) => {
+                error!("failed to send the digest of {}: {:?}", telegram_id, e);
+                Ok(false)
+              }
+            })
+        })
+        .fold(0, |sent, ok| Ok::<usize, Error>(if ok { sent + 1 } else { sent })),
+    )
+  }
+
+  fn send(
+    &self,
+    telegram_id: i64,
+    kitsu_id: i64,
+    settings: Settings,
+    since: DateTime<Utc>,
+  ) -> Box<Future<Item = bool, Error = Error>> {
+    let api = self.api.clone();
+    let bot = self.bot.clone();
+    let token = self.db.get_token(telegram_id, kitsu_id);
+    let week_ago = Utc::now() - Duration::weeks(1);
+    Box::new(
+      self
+        .api
+        .fetch_watching(token.clone(), kitsu_id)
+        .join(
+          self
+            .api
+            .fetch_library_events_since(token, kitsu_id, week_ago)
+            .then(move |res| match res {
+              Ok(events) => Ok(events),
+              // the rest of the digest is still worth sending
+              Err(e) => {
+                error!("failed to fetch the events of {}: {:?}", telegram_id, e);
+                Ok(Vec::new())
+              }
+            }),
+        )
+        .and_then(move |(shows, events)| {
+          // only shows still airing can have new episodes
+          let airing: Vec<String> = shows
+            .iter()
+            .filter(|&&(_, ref anime)| {
+              match anime.attributes.as_ref().and_then(|attr| attr.status.as_ref()) {
+                Some(&AnimeStatus::Current) => true,
+                _ => false,
+              }
+            })
+            .map(|&(_, ref anime)| anime.id.clone())
+            .collect();
+          stream::iter_ok(airing)
+            .and_then(move |anime_id| {
+              api.aired_episodes(&anime_id).then(move |res| match res {
+                Ok(episodes) => Ok((anime_id, episodes)),
+                Err(e) => {
+                  error!("failed to fetch the episodes of {}: {:?}", anime_id, e);
+                  Ok((anime_id, Vec::new()))
+                }
+              })
+            })
+            .collect()
+            .map(move |aired| {
+              let aired: HashMap<_, _> = aired.into_iter().collect();
+              (shows, events, aired)
+            })
+        })
+        .and_then(move |(shows, events, aired)| {
+          let digest = Digest::new(&settings, since, shows, events, aired);
+          if digest.is_empty() {
+            return Box::new(done(Ok(false))) as Box<Future<Item = bool, Error = Error>>;
+          }
+          let buttons = vec![
+            vec![
+              Button::new(
+                String::from("Open list"),
+                QueryCommand::Offset { kitsu_id, offset: 0 },
+              ),
+            ],
+          ];
+          Box::new(
+            bot
+              .send_message(
+                telegram_id,
+                parse_digest(&settings, &digest),
+                Some(ParseMode::HTML),
+                Some(bind_keyboard(buttons, telegram_id)),
+              )
+              .map(|msg| {
+                info!("send message: {:?} in {:?}", msg.text, msg.chat);
+                true
+              }),
+          )
+        }),
+    )
+  }
+}
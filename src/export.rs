@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+
+use serde_json::to_string_pretty;
+
+use types::ExportFormat;
+use types::kitsu::{pair_anime, Anime, AnimeSubtype, Entry, EntryStatus, Mapping};
+
+#[derive(Debug, Serialize)]
+pub struct ExportEntry {
+  pub kitsu_id: String,
+  pub mal_id: Option<String>,
+  pub title: String,
+  pub subtype: Option<String>,
+  pub episodes: Option<u32>,
+  pub status: Option<String>,
+  pub progress: i64,
+  pub score: Option<i64>,
+  pub started_at: Option<DateTime<Utc>>,
+  pub finished_at: Option<DateTime<Utc>>,
+  pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Joins library entries with their anime and MyAnimeList ids.
+pub fn collect_entries(
+  entries: Vec<Entry>,
+  animes: Vec<Anime>,
+  mappings: Vec<Mapping>,
+) -> Vec<ExportEntry> {
+  let mal_ids: HashMap<String, String> = mappings
+    .into_iter()
+    .filter(|m| m.attributes.external_site == "myanimelist/anime")
+    .map(|m| (m.id, m.attributes.external_id))
+    .collect();
+
+  pair_anime(entries, animes)
+    .into_iter()
+    .filter_map(|(entry, anime)| match (entry.attributes, anime.attributes) {
+      (Some(entry_attr), Some(anime_attr)) => {
+        let mal_id = anime
+          .relationships
+          .as_ref()
+          .and_then(|r| r.mappings.as_ref())
+          .and_then(|l| l.data.iter().filter_map(|i| mal_ids.get(&i.id)).next())
+          .cloned();
+        Some(ExportEntry {
+          mal_id,
+          kitsu_id: anime.id,
+          title: anime_attr.canonical_title,
+          subtype: anime_attr.subtype.map(|s| mal_type(&s).to_owned()),
+          episodes: anime_attr.episode_count,
+          status: entry_attr.status.map(|s| mal_status(&s).to_owned()),
+          progress: entry_attr.progress.unwrap_or(0),
+          score: entry_attr.rating_twenty.map(mal_score),
+          started_at: entry_attr.started_at,
+          finished_at: entry_attr.finished_at,
+          updated_at: entry_attr.updated_at,
+        })
+      }
+      _ => None,
+    })
+    .collect()
+}
+
+pub fn mal_status(status: &EntryStatus) -> &'static str {
+  match *status {
+    EntryStatus::Current => "Watching",
+    EntryStatus::Completed => "Completed",
+    EntryStatus::OnHold => "On-Hold",
+    EntryStatus::Dropped => "Dropped",
+    EntryStatus::Planned | EntryStatus::Unknown => "Plan to Watch",
+  }
+}
+
+/// Kitsu rates out of 20, MyAnimeList out of 10; halves round up.
+fn mal_score(rating_twenty: i64) -> i64 {
+  (rating_twenty + 1) / 2
+}
+
+fn mal_type(subtype: &AnimeSubtype) -> &'static str {
+  match *subtype {
+    AnimeSubtype::TV => "TV",
+    AnimeSubtype::OVA => "OVA",
+    AnimeSubtype::ONA => "ONA",
+    AnimeSubtype::Movie => "Movie",
+    AnimeSubtype::Music => "Music",
+    AnimeSubtype::Special => "Special",
+    AnimeSubtype::Unknown => "Unknown",
+  }
+}
+
+fn mal_date(date: &Option<DateTime<Utc>>) -> String {
+  date.map_or(String::from("0000-00-00"), |d| d.format("%Y-%m-%d").to_string())
+}
+
+fn cdata(text: &str) -> String {
+  format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+fn csv_field(field: &str) -> String {
+  if field.contains(',') || field.contains('"') || field.contains('\n') {
+    format!("\"{}\"", field.replace("\"", "\"\""))
+  } else {
+    field.to_owned()
+  }
+}
+
+/// Renders the entries in the layout accepted by MyAnimeList's importer.
+/// Entries without a MyAnimeList id can't be imported and are skipped.
+pub fn to_mal_xml(entries: &[ExportEntry]) -> String {
+  let mut xml = String::from(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n\
+     <myanimelist>\n\
+     \t<myinfo>\n\
+     \t\t<user_export_type>1</user_export_type>\n\
+     \t</myinfo>\n",
+  );
+  for entry in entries {
+    let mal_id = match entry.mal_id {
+      Some(ref mal_id) => mal_id,
+      None => continue,
+    };
+    xml.push_str(&format!(
+      "\t<anime>\n\
+       \t\t<series_animedb_id>{}</series_animedb_id>\n\
+       \t\t<series_title>{}</series_title>\n\
+       \t\t<series_type>{}</series_type>\n\
+       \t\t<series_episodes>{}</series_episodes>\n\
+       \t\t<my_id>0</my_id>\n\
+       \t\t<my_watched_episodes>{}</my_watched_episodes>\n\
+       \t\t<my_start_date>{}</my_start_date>\n\
+       \t\t<my_finish_date>{}</my_finish_date>\n\
+       \t\t<my_score>{}</my_score>\n\
+       \t\t<my_status>{}</my_status>\n\
+       \t\t<my_times_watched>0</my_times_watched>\n\
+       \t\t<my_rewatching>0</my_rewatching>\n\
+       \t\t<update_on_import>1</update_on_import>\n\
+       \t</anime>\n",
+      mal_id,
+      cdata(&entry.title),
+      entry.subtype.as_ref().map_or("Unknown", |s| s.as_str()),
+      entry.episodes.unwrap_or(0),
+      entry.progress,
+      mal_date(&entry.started_at),
+      mal_date(&entry.finished_at),
+      entry.score.unwrap_or(0),
+      entry.status.as_ref().map_or("Plan to Watch", |s| s.as_str()),
+    ));
+  }
+  xml.push_str("</myanimelist>\n");
+  xml
+}
+
+pub fn to_csv(entries: &[ExportEntry]) -> String {
+  let mut csv = String::from(
+    "kitsu_id,mal_id,title,type,episodes,status,progress,score,started_at,finished_at,updated_at\n",
+  );
+  for entry in entries {
+    let fields = vec![
+      entry.kitsu_id.clone(),
+      entry.mal_id.clone().unwrap_or_default(),
+      entry.title.clone(),
+      entry.subtype.clone().unwrap_or_default(),
+      entry.episodes.map_or(String::new(), |e| e.to_string()),
+      entry.status.clone().unwrap_or_default(),
+      entry.progress.to_string(),
+      entry.score.map_or(String::new(), |s| s.to_string()),
+      entry.started_at.map_or(String::new(), |d| d.to_rfc3339()),
+      entry.finished_at.map_or(String::new(), |d| d.to_rfc3339()),
+      entry.updated_at.map_or(String::new(), |d| d.to_rfc3339()),
+    ];
+    csv.push_str(
+      &fields
+        .iter()
+        .map(|f| csv_field(f))
+        .collect::<Vec<_>>()
+        .join(","),
+    );
+    csv.push('\n');
+  }
+  csv
+}
+
+/// Returns the file name and content of the export.
+pub fn export(format: &ExportFormat, entries: &[ExportEntry]) -> (String, String) {
+  match *format {
+    ExportFormat::Mal => (String::from("animelist.xml"), to_mal_xml(entries)),
+    ExportFormat::Csv => (String::from("animelist.csv"), to_csv(entries)),
+    ExportFormat::Json => (
+      String::from("animelist.json"),
+      to_string_pretty(entries).expect("error/json-to-string"),
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{mal_score, mal_status, to_mal_xml, ExportEntry};
+  use import::{parse_mal_status, parse_mal_xml};
+  use types::kitsu::EntryStatus;
+
+  fn entry(mal_id: Option<&str>, title: &str, status: &str, progress: i64) -> ExportEntry {
+    ExportEntry {
+      kitsu_id: String::from("1"),
+      mal_id: mal_id.map(|id| id.to_owned()),
+      title: title.to_owned(),
+      subtype: Some(String::from("TV")),
+      episodes: Some(12),
+      status: Some(status.to_owned()),
+      progress,
+      score: Some(8),
+      started_at: None,
+      finished_at: None,
+      updated_at: None,
+    }
+  }
+
+  #[test]
+  fn mal_xml_round_trip() {
+    let xml = to_mal_xml(&[
+      entry(Some("32901"), "Eromanga Sensei", "Watching", 3),
+      entry(Some("5114"), "Fullmetal Alchemist: Brotherhood & <more>", "Completed", 64),
+    ]);
+    let entries = parse_mal_xml(&xml);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].mal_id, "32901");
+    assert_eq!(entries[0].title, "Eromanga Sensei");
+    assert_eq!(entries[0].status, EntryStatus::Current);
+    assert_eq!(entries[0].progress, 3);
+    assert_eq!(entries[0].score, 8);
+    assert_eq!(entries[1].title, "Fullmetal Alchemist: Brotherhood & <more>");
+    assert_eq!(entries[1].status, EntryStatus::Completed);
+    assert_eq!(entries[1].progress, 64);
+  }
+
+  #[test]
+  fn mal_xml_skips_entries_without_mal_id() {
+    let xml = to_mal_xml(&[entry(None, "Kitsu Only", "Watching", 1)]);
+    assert!(!xml.contains("Kitsu Only"));
+    assert!(parse_mal_xml(&xml).is_empty());
+  }
+
+  #[test]
+  fn statuses_survive_a_round_trip() {
+    for status in &[
+      EntryStatus::Current,
+      EntryStatus::Completed,
+      EntryStatus::OnHold,
+      EntryStatus::Dropped,
+      EntryStatus::Planned,
+    ] {
+      assert_eq!(parse_mal_status(mal_status(status)).as_ref(), Some(status));
+    }
+    // MyAnimeList has no unknown status
+    assert_eq!(mal_status(&EntryStatus::Unknown), "Plan to Watch");
+  }
+
+  #[test]
+  fn scores_are_halved_rounding_up() {
+    assert_eq!(mal_score(20), 10);
+    assert_eq!(mal_score(16), 8);
+    assert_eq!(mal_score(15), 8);
+    assert_eq!(mal_score(1), 1);
+  }
+}
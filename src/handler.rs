@@ -1,43 +1,149 @@
+use std::rc::Rc;
+use std::str::FromStr;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Duration, Utc};
+
 use nom::IResult;
-use futures::{done, Future};
+use futures::{done, stream, Future, Stream};
+use futures::future::join_all;
 
+use admin::Admin;
 use bot::telegram::Bot;
+use callback::CallbackRegistry;
 use kitsu::Api;
 use error::{Error, TelegramError};
-use types::{Client, MsgCommand, QueryCommand};
+use types::{bind_keyboard, Button, Client, ExportFormat, Keyboard, MsgCommand, Prompt, QueryCommand,
+            Season, PROMPT_TTL_MINUTES};
 use utils::*;
-use types::telegram::{CallbackQuery, InlineKeyboardButton, Message, ParseMode};
+use types::kitsu::{EntryAttributes, EntryStatus, TitleLanguagePreference, User as KitsuUser};
+use types::telegram::{CallbackQuery, ChatMemberStatus, Document, Message, ParseMode};
 use database::Database;
+use export;
+use import::{self, ImportAction, ImportPlan};
+use undo::{Change, UndoLog};
+use settings::{parse_timezone, SettingChange, SettingsStore};
+use notify::NotifyStore;
+use scheduler::Scheduler;
+use templates::Templates;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
+/// Prompts waiting for an answer, by chat and message id, with when they
+/// were asked.
+type Prompts = Rc<RefCell<HashMap<(i64, i64), (DateTime<Utc>, Prompt)>>>;
+
 pub struct Handler {
   api: Api,
   bot: Bot,
   db: Database,
+  callbacks: CallbackRegistry,
+  admin: Admin,
+  settings: SettingsStore,
+  notify: NotifyStore,
+  scheduler: Scheduler,
+  templates: Templates,
+  imports: Rc<RefCell<HashMap<i64, ImportPlan>>>,
+  prompts: Prompts,
+  undo: UndoLog,
 }
 
 impl Handler {
-  pub fn new(bot: Bot, client: Client, token: String) -> Handler {
+  pub fn new(
+    bot: Bot,
+    client: Client,
+    db: Database,
+    callbacks: CallbackRegistry,
+    admin: Admin,
+    settings: SettingsStore,
+    notify: NotifyStore,
+    scheduler: Scheduler,
+    templates: Templates,
+  ) -> Handler {
     Handler {
+      db,
       bot,
-      api: Api::new(client.clone()),
-      db: Database::new(token, client),
+      admin,
+      settings,
+      notify,
+      scheduler,
+      templates,
+      callbacks,
+      api: Api::new(client),
+      imports: Rc::new(RefCell::new(HashMap::new())),
+      prompts: Rc::new(RefCell::new(HashMap::new())),
+      undo: UndoLog::new(),
     }
   }
 
   pub fn handle_message(&mut self, msg: Message) -> Box<Future<Item = (), Error = Error>> {
     let chat_id = msg.chat.unwrap().id;
     let user_id = msg.from.unwrap().id;
-    let text = msg.text.unwrap_or(String::new());
+    let text = msg.text.unwrap_or_default();
+
+    if self.admin.is_banned(user_id) {
+      info!("ignored message from banned user {}", user_id);
+      return Box::new(done(Ok(())));
+    }
+    if self.admin.in_maintenance() && !self.admin.is_admin(user_id) {
+      let text = self.templates.render("maintenance", &[]);
+      return self.reply(chat_id, text);
+    }
+
+    if let Some(document) = msg.document {
+      info!("received document: {:?} from {}", document.file_name, user_id);
+      if !import::is_mal_export(&document) {
+        return Box::new(done(Ok(())));
+      }
+      return self.import(user_id, chat_id, document);
+    }
+
+    let prompt = msg.reply_to_message.and_then(|reply| reply.message_id).and_then(
+      |reply_id| {
+        let mut prompts = self.prompts.borrow_mut();
+        match prompts.get(&(chat_id, reply_id)) {
+          Some(&(asked_at, ref prompt)) if prompt.user_id() == user_id && !is_stale(asked_at) => (),
+          _ => return None,
+        }
+        prompts.remove(&(chat_id, reply_id)).map(|(_, prompt)| prompt)
+      },
+    );
+    if let Some(prompt) = prompt {
+      info!("received answer: '{}' from {}", text, user_id);
+      return self.answer(user_id, chat_id, prompt, text);
+    }
 
     info!("received message: '{}' from {}, in {}", text, user_id, text);
 
     match parse_message(&text) {
+      IResult::Done(_, ref command) if command.is_operator() && !self.admin.is_admin(user_id) => {
+        self.reply(chat_id, self.templates.render("admin_only", &[]))
+      }
       IResult::Done(_, command) => match command {
         MsgCommand::List => self.list(user_id, chat_id),
         MsgCommand::Update => self.update(chat_id),
         MsgCommand::Version => self.version(chat_id),
+        MsgCommand::Export(format) => self.export(user_id, chat_id, format),
+        MsgCommand::Accounts => self.accounts(user_id, chat_id),
+        MsgCommand::Use(name) => self.use_account(user_id, chat_id, name),
+        MsgCommand::Timezone(name) => self.set_timezone(user_id, chat_id, name),
+        MsgCommand::Trending => self.trending(user_id, chat_id),
+        MsgCommand::Season { season, year } => self.season(user_id, chat_id, season, year),
+        MsgCommand::Shared(shared) => self.shared(user_id, chat_id, shared),
+        MsgCommand::Users => self.users(chat_id),
+        MsgCommand::Ban(target) => self.ban(chat_id, target),
+        MsgCommand::Unban(target) => self.unban(chat_id, target),
+        MsgCommand::Broadcast(text) => self.broadcast(chat_id, text),
+        MsgCommand::Maintenance(on) => self.maintenance(chat_id, on),
+        MsgCommand::RotateKey => self.rotate_key(chat_id),
+        MsgCommand::Jobs => self.jobs(chat_id),
+        MsgCommand::Unregister => self.unregister(user_id, chat_id),
+        MsgCommand::Settings => self.show_settings(user_id, chat_id),
+        MsgCommand::History => self.history(user_id, chat_id),
+        MsgCommand::Progress { title, progress } => {
+          self.progress_by_title(user_id, chat_id, title, progress)
+        }
       },
       _ => self.unknown(chat_id),
     }
@@ -46,40 +152,197 @@ impl Handler {
   pub fn handle_query(&mut self, query: CallbackQuery) -> Box<Future<Item = (), Error = Error>> {
     let query_id = query.id;
     let user_id = query.from.id;
-    let data = query.data.unwrap_or(String::new());
+    let first_name = query.from.first_name;
+    let data = query.data.unwrap_or_default();
 
     info!("received query: '{}' from {}", data, user_id);
 
+    if self.admin.is_banned(user_id) {
+      info!("ignored query from banned user {}", user_id);
+      return Box::new(done(Ok(())));
+    }
+    if self.admin.in_maintenance() && !self.admin.is_admin(user_id) {
+      let text = self.templates.render("maintenance", &[]);
+      return Box::new(
+        self
+          .bot
+          .answer_query(query_id, Some(text), Some(true))
+          .and_then(|_| Ok(())),
+      );
+    }
+
     match query.message {
       Some(msg) => {
         let msg_id = msg.message_id.unwrap();
         let chat_id = msg.chat.unwrap().id;
+        let has_photo = msg.photo.is_some();
 
-        match parse_query(&data) {
-          IResult::Done(_, command) => match command {
-            QueryCommand::Offset { kitsu_id, offset } => {
-              self.offset(msg_id, chat_id, kitsu_id, offset, query_id)
-            }
-            QueryCommand::Detail { kitsu_id, anime_id } => {
-              self.detail(msg_id, chat_id, kitsu_id, anime_id, query_id)
-            }
-            QueryCommand::Progress {
-              kitsu_id,
-              anime_id,
-              entry_id,
-              progress,
-            } => self.progress(
-              msg_id,
-              chat_id,
-              user_id,
-              kitsu_id,
-              anime_id,
-              progress,
-              entry_id,
-              query_id,
-            ),
-          },
-          _ => self.unknown(chat_id),
+        let callback = match self.callbacks.parse_query(&data) {
+          Some(callback) => callback,
+          None => return self.expired(query_id),
+        };
+        let owner = callback.owner.unwrap_or(user_id);
+        let shared = callback.command.is_browsing() && self.settings.is_shared(chat_id);
+        if owner != user_id && !shared {
+          return self.not_owner(query_id);
+        }
+
+        match callback.command {
+          QueryCommand::Offset { kitsu_id, offset } => {
+            self.offset(msg_id, chat_id, has_photo, owner, kitsu_id, offset, query_id)
+          }
+          QueryCommand::History { kitsu_id, offset } => {
+            self.history_page(msg_id, chat_id, has_photo, owner, kitsu_id, offset, query_id)
+          }
+          QueryCommand::Detail { kitsu_id, anime_id } => {
+            self.detail(msg_id, chat_id, has_photo, owner, kitsu_id, anime_id, query_id)
+          }
+          QueryCommand::Progress {
+            kitsu_id,
+            anime_id,
+            entry_id,
+            progress,
+          } => self.progress(
+            msg_id,
+            chat_id,
+            has_photo,
+            user_id,
+            kitsu_id,
+            anime_id,
+            progress,
+            entry_id,
+            query_id,
+          ),
+          QueryCommand::SetProgress {
+            kitsu_id,
+            anime_id,
+            entry_id,
+          } => self.prompt_progress(
+            chat_id,
+            user_id,
+            first_name,
+            kitsu_id,
+            anime_id,
+            entry_id,
+            query_id,
+          ),
+          QueryCommand::Rewatch {
+            kitsu_id,
+            anime_id,
+            entry_id,
+          } => self.rewatch(
+            msg_id,
+            chat_id,
+            has_photo,
+            user_id,
+            kitsu_id,
+            anime_id,
+            entry_id,
+            query_id,
+          ),
+          QueryCommand::Notes {
+            kitsu_id,
+            anime_id,
+            entry_id,
+          } => self.prompt_notes(
+            chat_id,
+            user_id,
+            first_name,
+            kitsu_id,
+            anime_id,
+            entry_id,
+            query_id,
+          ),
+          QueryCommand::Private {
+            kitsu_id,
+            anime_id,
+            entry_id,
+            private,
+          } => self.private(
+            msg_id,
+            chat_id,
+            has_photo,
+            user_id,
+            kitsu_id,
+            anime_id,
+            entry_id,
+            private,
+            query_id,
+          ),
+          QueryCommand::Season {
+            kitsu_id,
+            season,
+            year,
+            offset,
+          } => self.season_page(
+            msg_id,
+            chat_id,
+            has_photo,
+            owner,
+            kitsu_id,
+            season,
+            year,
+            offset,
+            query_id,
+          ),
+          QueryCommand::Mute {
+            kitsu_id,
+            anime_id,
+            entry_id,
+            episode,
+            muted,
+          } => self.mute(
+            msg_id,
+            chat_id,
+            msg.text.unwrap_or_default(),
+            user_id,
+            kitsu_id,
+            anime_id,
+            entry_id,
+            episode,
+            muted,
+            query_id,
+          ),
+          QueryCommand::SetStatus {
+            kitsu_id,
+            anime_id,
+            entry_id,
+            status,
+          } => self.set_status(
+            msg_id,
+            chat_id,
+            has_photo,
+            user_id,
+            kitsu_id,
+            anime_id,
+            entry_id,
+            status,
+            query_id,
+          ),
+          QueryCommand::Plan { kitsu_id, anime_id } => {
+            self.plan(user_id, kitsu_id, anime_id, query_id)
+          }
+          QueryCommand::Undo { kitsu_id, change_id } => self.undo(
+            msg_id,
+            chat_id,
+            has_photo,
+            user_id,
+            kitsu_id,
+            change_id,
+            query_id,
+          ),
+          QueryCommand::Import { kitsu_id, start } => {
+            self.run_import(msg_id, chat_id, user_id, kitsu_id, start, query_id)
+          }
+          QueryCommand::Setting(change) => {
+            self.change_setting(msg_id, chat_id, has_photo, user_id, Some(change), query_id)
+          }
+          QueryCommand::SettingsDone => {
+            self.change_setting(msg_id, chat_id, has_photo, user_id, None, query_id)
+          }
+          QueryCommand::Unregister { confirm } => {
+            self.run_unregister(msg_id, chat_id, has_photo, user_id, confirm, query_id)
+          }
         }
       }
       None => Box::new(done::<_, Error>(
@@ -88,11 +351,85 @@ impl Handler {
     }
   }
 
+  fn non_registered(&self, user_id: i64) -> String {
+    self
+      .templates
+      .render("non_registered", &[("user_id", &user_id.to_string())])
+  }
+
   fn unknown(&self, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
     Box::new(
       self
         .bot
-        .send_message(chat_id, String::from("Unknown command."), None, None)
+        .send_message(chat_id, self.templates.render("unknown_command", &[]), None, None)
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn expired(&self, query_id: String) -> Box<Future<Item = (), Error = Error>> {
+    Box::new(
+      self
+        .bot
+        .answer_query(
+          query_id,
+          Some(self.templates.render("button_expired", &[])),
+          Some(true),
+        )
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn not_owner(&self, query_id: String) -> Box<Future<Item = (), Error = Error>> {
+    Box::new(
+      self
+        .bot
+        .answer_query(
+          query_id,
+          Some(self.templates.render("button_not_owner", &[])),
+          Some(true),
+        )
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn shared(
+    &self,
+    user_id: i64,
+    chat_id: i64,
+    shared: Option<bool>,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    // private chats share their id with the user
+    if chat_id == user_id {
+      return self.reply(chat_id, self.templates.render("shared_private_chat", &[]));
+    }
+    let shared = match shared {
+      Some(shared) => shared,
+      None => {
+        let status = if self.settings.is_shared(chat_id) { "on" } else { "off" };
+        let text = self.templates.render("shared_status", &[("status", status)]);
+        return self.reply(chat_id, text);
+      }
+    };
+    let bot = self.bot.clone();
+    let settings = self.settings.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .bot
+        .get_chat_member(chat_id, user_id)
+        .and_then(move |member| {
+          let text = match member.status {
+            ChatMemberStatus::Creator | ChatMemberStatus::Administrator => {
+              settings.set_shared(chat_id, user_id, shared);
+              templates.render(if shared { "shared_on" } else { "shared_off" }, &[])
+            }
+            _ => templates.render("shared_not_admin", &[]),
+          };
+          bot.send_message(chat_id, text, None, None)
+        })
         .and_then(|msg| {
           info!("send message: {:?} in {:?}", msg.text, msg.chat);
           Ok(())
@@ -106,10 +443,7 @@ impl Handler {
         .bot
         .send_message(
           chat_id,
-          format!(
-            "<pre>Sagiri-{}\nFor more information, please visit the wiki.</pre>",
-            VERSION
-          ),
+          self.templates.render("version", &[("version", VERSION)]),
           Some(ParseMode::HTML),
           None,
         )
@@ -122,15 +456,12 @@ impl Handler {
 
   fn list(&mut self, user_id: i64, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
     let bot = self.bot.clone();
+    let settings = self.settings.get(user_id);
+    let templates = self.templates.clone();
     match self.db.get_kitsu_id(user_id) {
       None => Box::new(
         bot
-          .send_message(
-            chat_id,
-            format!("Non-registered user: {}", user_id),
-            None,
-            None,
-          )
+          .send_message(chat_id, self.non_registered(user_id), None, None)
           .and_then(|msg| {
             info!("send message: {:?} in {:?}", msg.text, msg.chat);
             Ok(())
@@ -138,13 +469,32 @@ impl Handler {
       ),
       Some(kitsu_id) => Box::new(
         self
-          .api
-          .fetch_anime(kitsu_id, 0)
-          .and_then(move |(prev, next, entries, animes)| {
-            Ok(parse_anime_list(kitsu_id, prev, next, entries, animes))
+          .title_preference(user_id, kitsu_id)
+          .join(self.api.fetch_anime(
+            self.db.get_token(user_id, kitsu_id),
+            kitsu_id,
+            0,
+            settings.page_size,
+          ))
+          .and_then(move |(preference, (prev, next, entries, animes))| {
+            Ok(parse_anime_list(
+              kitsu_id,
+              &preference,
+              &settings,
+              &templates,
+              prev,
+              next,
+              entries,
+              animes,
+            ))
           })
           .and_then(move |(text, buttons)| {
-            bot.send_message(chat_id, text, Some(ParseMode::HTML), Some(buttons))
+            bot.send_message(
+              chat_id,
+              text,
+              Some(ParseMode::HTML),
+              Some(bind_keyboard(buttons, user_id)),
+            )
           })
           .and_then(|msg| {
             info!("send message: {:?} in {:?}", msg.text, msg.chat);
@@ -154,18 +504,31 @@ impl Handler {
     }
   }
 
-  fn update(&mut self, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+  fn history(&mut self, user_id: i64, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let kitsu_id = match self.db.get_kitsu_id(user_id) {
+      Some(kitsu_id) => kitsu_id,
+      None => return self.reply(chat_id, self.non_registered(user_id)),
+    };
     let bot = self.bot.clone();
+    let settings = self.settings.get(user_id);
     Box::new(
       self
-        .db
-        .fetch()
-        .and_then(move |users| {
+        .title_preference(user_id, kitsu_id)
+        .join(self.api.fetch_library_events(
+          self.db.get_token(user_id, kitsu_id),
+          kitsu_id,
+          0,
+          settings.page_size,
+        ))
+        .and_then(move |(preference, (prev, next, events, animes))| {
+          Ok(parse_history(kitsu_id, &preference, &settings, prev, next, events, animes))
+        })
+        .and_then(move |(text, buttons)| {
           bot.send_message(
             chat_id,
-            format!("<pre>Successful update: {} user(s)</pre>", users.len()),
+            text,
             Some(ParseMode::HTML),
-            None,
+            Some(bind_keyboard(buttons, user_id)),
           )
         })
         .and_then(|msg| {
@@ -175,105 +538,1763 @@ impl Handler {
     )
   }
 
-  fn offset(
+  fn history_page(
     &self,
     msg_id: i64,
     chat_id: i64,
+    has_photo: bool,
+    owner: i64,
     kitsu_id: i64,
     offset: i64,
     query_id: String,
   ) -> Box<Future<Item = (), Error = Error>> {
     let bot1 = self.bot.clone();
     let bot2 = self.bot.clone();
+    let settings = self.settings.get(owner);
     Box::new(
       self
-        .api
-        .fetch_anime(kitsu_id, offset)
-        .and_then(move |(prev, next, entries, animes)| {
-          Ok(parse_anime_list(kitsu_id, prev, next, entries, animes))
+        .title_preference(owner, kitsu_id)
+        .join(self.api.fetch_library_events(
+          self.db.get_token(owner, kitsu_id),
+          kitsu_id,
+          offset,
+          settings.page_size,
+        ))
+        .and_then(move |(preference, (prev, next, events, animes))| {
+          Ok(parse_history(kitsu_id, &preference, &settings, prev, next, events, animes))
         })
         .and_then(move |(text, buttons)| {
-          bot1.edit_inline_keyboard(msg_id, chat_id, text, Some(ParseMode::HTML), Some(buttons))
+          bot1.replace_message(
+            msg_id,
+            chat_id,
+            has_photo,
+            text,
+            Some(ParseMode::HTML),
+            Some(bind_keyboard(buttons, owner)),
+          )
         })
         .and_then(move |_| bot2.answer_query(query_id, None, None))
         .and_then(|_| Ok(())),
     )
   }
 
-  fn detail(
-    &self,
-    msg_id: i64,
+  fn export(
+    &mut self,
+    user_id: i64,
     chat_id: i64,
-    kitsu_id: i64,
-    anime_id: i64,
-    query_id: String,
+    format: ExportFormat,
   ) -> Box<Future<Item = (), Error = Error>> {
     let bot = self.bot.clone();
-    Box::new(
-      self
-        .api
-        .get_anime(kitsu_id, anime_id)
-        .and_then(move |pair| Ok(parse_anime_detail(kitsu_id, pair)))
-        .and_then(move |(text, buttons)| {
-          bot.edit_inline_keyboard(msg_id, chat_id, text, Some(ParseMode::HTML), Some(buttons))
-        })
-        .and_then(|msg| {
-          info!("send message: {:?} in {:?}", msg.text, msg.chat);
-          Ok(())
-        }),
-    )
+    let templates = self.templates.clone();
+    match self.db.get_kitsu_id(user_id) {
+      None => Box::new(
+        bot
+          .send_message(chat_id, self.non_registered(user_id), None, None)
+          .and_then(|msg| {
+            info!("send message: {:?} in {:?}", msg.text, msg.chat);
+            Ok(())
+          }),
+      ),
+      Some(kitsu_id) => Box::new(
+        self
+          .api
+          .fetch_library(self.db.get_token(user_id, kitsu_id), kitsu_id)
+          .and_then(move |(entries, animes, mappings)| {
+            let entries = export::collect_entries(entries, animes, mappings);
+            let (file_name, content) = export::export(&format, &entries);
+            bot.send_document(
+              chat_id,
+              file_name,
+              content.into_bytes(),
+              Some(templates.render("export_done", &[("count", &entries.len().to_string())])),
+            )
+          })
+          .and_then(|msg| {
+            info!("send document: {:?} in {:?}", msg.caption, msg.chat);
+            Ok(())
+          }),
+      ),
+    }
   }
 
-  fn progress(
+  fn import(
+    &mut self,
+    user_id: i64,
+    chat_id: i64,
+    document: Document,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot = self.bot.clone();
+    match self.db.get_kitsu_id(user_id) {
+      None => Box::new(
+        bot
+          .send_message(chat_id, self.non_registered(user_id), None, None)
+          .and_then(|msg| {
+            info!("send message: {:?} in {:?}", msg.text, msg.chat);
+            Ok(())
+          }),
+      ),
+      Some(kitsu_id) => {
+        let api1 = self.api.clone();
+        let api2 = self.api.clone();
+        let token = self.db.get_token(user_id, kitsu_id);
+        let bot1 = bot.clone();
+        let bot2 = bot.clone();
+        let imports = self.imports.clone();
+        Box::new(
+          bot
+            .get_file(document.file_id)
+            .and_then(move |file| match file.file_path {
+              Some(path) => bot1.download_file(&path),
+              None => Box::new(done::<Vec<u8>, Error>(Err(
+                TelegramError::new("File is too big.".to_owned()),
+              ))),
+            })
+            .and_then(move |content| {
+              let entries = import::parse_mal_xml(&String::from_utf8_lossy(&content));
+              let batches: Vec<Vec<String>> = entries
+                .chunks(20)
+                .map(|chunk| chunk.iter().map(|e| e.mal_id.clone()).collect())
+                .collect();
+              stream::iter_ok::<_, Error>(batches)
+                .and_then(move |batch| api1.find_mal_mappings(&batch))
+                .concat2()
+                .join(api2.fetch_library(token, kitsu_id))
+                .map(move |(mappings, (library, _, _))| {
+                  import::plan(kitsu_id, entries, mappings, library)
+                })
+            })
+            .and_then(move |plan| {
+              let text = import::summary(&plan);
+              let buttons = if plan.items.is_empty() {
+                vec![]
+              } else {
+                vec![
+                  vec![
+                    Button::new(
+                      String::from("Start Import"),
+                      QueryCommand::Import { kitsu_id, start: true },
+                    ),
+                    Button::new(
+                      String::from("Cancel"),
+                      QueryCommand::Import { kitsu_id, start: false },
+                    ),
+                  ],
+                ]
+              };
+              {
+                let mut imports = imports.borrow_mut();
+                imports.retain(|_, old| !old.is_expired());
+                imports.insert(user_id, plan);
+              }
+              bot2.send_message(
+                chat_id,
+                text,
+                Some(ParseMode::HTML),
+                Some(bind_keyboard(buttons, user_id)),
+              )
+            })
+            .and_then(|msg| {
+              info!("send message: {:?} in {:?}", msg.text, msg.chat);
+              Ok(())
+            }),
+        )
+      }
+    }
+  }
+
+  fn run_import(
     &mut self,
     msg_id: i64,
     chat_id: i64,
     user_id: i64,
     kitsu_id: i64,
-    anime_id: String,
-    progress: i64,
-    entry_id: String,
+    start: bool,
     query_id: String,
   ) -> Box<Future<Item = (), Error = Error>> {
     let bot = self.bot.clone();
+    let templates = self.templates.clone();
+    let plan = match self.imports.borrow_mut().remove(&user_id) {
+      Some(ref plan) if plan.kitsu_id != kitsu_id || plan.is_expired() => None,
+      plan => plan,
+    };
     let token = self.db.get_token(user_id, kitsu_id);
-    let text = format!("Successful update to episode {}", progress);
-    let buttons = vec![
-      vec![
-        InlineKeyboardButton::with_callback_data(
-          "back to anime".to_owned(),
-          format!("/{}/detail/{}/", kitsu_id, anime_id),
-        ),
-      ],
-      vec![
-        InlineKeyboardButton::with_callback_data(
-          "back to list".to_owned(),
-          format!("/{}/offset/0/", kitsu_id),
-        ),
-      ],
-    ];
-    match token {
-      None => Box::new(
+    match (plan, token) {
+      (None, _) => Box::new(
         bot
           .answer_query(
             query_id,
-            Some(String::from("Non-registered user")),
+            Some(templates.render("import_expired", &[])),
             Some(true),
           )
           .and_then(|_| Ok(())),
       ),
-      Some(token) => Box::new(
-        self
-          .api
-          .update_anime_entry(token, entry_id, progress, anime_id)
+      (Some(_), None) => Box::new(
+        bot
+          .answer_query(
+            query_id,
+            Some(self.templates.render("non_registered_alert", &[])),
+            Some(true),
+          )
+          .and_then(|_| Ok(())),
+      ),
+      (Some(_), Some(_)) if !start => Box::new(
+        bot
+          .answer_query(query_id, None, None)
           .and_then(move |_| {
-            bot.edit_inline_keyboard(msg_id, chat_id, text, Some(ParseMode::HTML), Some(buttons))
+            let text = templates.render("import_cancelled", &[]);
+            bot.edit_inline_keyboard(msg_id, chat_id, text, None, None)
           })
-          .and_then(|msg| {
-            info!("send message: {:?} in {:?}", msg.text, msg.chat);
-            Ok(())
-          }),
+          .and_then(|_| Ok(())),
       ),
+      (Some(plan), Some(token)) => {
+        let api = self.api.clone();
+        let bot1 = bot.clone();
+        let bot2 = bot.clone();
+        let total = plan.items.len();
+        let progress = templates.clone();
+        Box::new(
+          bot
+            .answer_query(query_id, Some(templates.render("import_started", &[])), None)
+            .and_then(move |_| {
+              stream::iter_ok::<_, Error>(plan.items.into_iter().enumerate())
+                .and_then(move |(i, item)| {
+                  let save = match item.action {
+                    ImportAction::Create => api.create_entry(
+                      token.clone(),
+                      kitsu_id,
+                      item.anime_id,
+                      item.entry.attributes(),
+                    ),
+                    ImportAction::Update(entry_id) => {
+                      api.update_entry(token.clone(), entry_id, item.entry.attributes())
+                    }
+                  };
+                  let mal_id = item.entry.mal_id;
+                  save.then(move |res| {
+                    if let Err(ref e) = res {
+                      error!("failed to import mal #{}: {:?}", mal_id, e);
+                    }
+                    Ok::<_, Error>((i, res.is_ok()))
+                  })
+                })
+                .fold(0, move |failed, (i, ok)| {
+                  let failed = if ok { failed } else { failed + 1 };
+                  if (i + 1) % 20 == 0 && i + 1 < total {
+                    Box::new(
+                      bot1
+                        .clone()
+                        .edit_inline_keyboard(
+                          msg_id,
+                          chat_id,
+                          progress.render(
+                            "import_progress",
+                            &[("done", &(i + 1).to_string()), ("total", &total.to_string())],
+                          ),
+                          None,
+                          None,
+                        )
+                        .map(move |_| failed),
+                    ) as Box<Future<Item = usize, Error = Error>>
+                  } else {
+                    Box::new(done(Ok(failed)))
+                  }
+                })
+            })
+            .and_then(move |failed| {
+              bot2.edit_inline_keyboard(
+                msg_id,
+                chat_id,
+                templates.render(
+                  "import_finished",
+                  &[
+                    ("imported", &(total - failed).to_string()),
+                    ("failed", &failed.to_string()),
+                  ],
+                ),
+                None,
+                None,
+              )
+            })
+            .and_then(|msg| {
+              info!("send message: {:?} in {:?}", msg.text, msg.chat);
+              Ok(())
+            }),
+        )
+      }
+    }
+  }
+
+  fn fetch_accounts(&self, user_id: i64) -> Box<Future<Item = Vec<KitsuUser>, Error = Error>> {
+    let users: Vec<_> = self
+      .db
+      .get_kitsu_ids(user_id)
+      .into_iter()
+      .map(|kitsu_id| self.api.get_user(kitsu_id))
+      .collect();
+    Box::new(join_all(users))
+  }
+
+  fn accounts(&mut self, user_id: i64, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let active = match self.db.get_kitsu_id(user_id) {
+      Some(kitsu_id) => kitsu_id,
+      None => return self.reply(chat_id, self.non_registered(user_id)),
+    };
+    let bot = self.bot.clone();
+    Box::new(
+      self
+        .fetch_accounts(user_id)
+        .and_then(move |users| {
+          let text = parse_accounts(Some(active), users);
+          bot.send_message(chat_id, text, Some(ParseMode::HTML), None)
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn unregister(&mut self, user_id: i64, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    if self.db.get_kitsu_id(user_id).is_none() {
+      return self.reply(chat_id, self.non_registered(user_id));
     }
+    let text = self.templates.render("unregister_confirm", &[]);
+    let buttons = vec![
+      vec![
+        Button::new(
+          String::from("Delete My Data"),
+          QueryCommand::Unregister { confirm: true },
+        ),
+        Button::new(
+          String::from("Cancel"),
+          QueryCommand::Unregister { confirm: false },
+        ),
+      ],
+    ];
+    Box::new(
+      self
+        .bot
+        .send_message(chat_id, text, None, Some(bind_keyboard(buttons, user_id)))
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
   }
+
+  fn run_unregister(
+    &mut self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    user_id: i64,
+    confirm: bool,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    if !confirm {
+      return Box::new(
+        bot1
+          .replace_message(
+            msg_id,
+            chat_id,
+            has_photo,
+            self.templates.render("unregister_cancelled", &[]),
+            None,
+            None,
+          )
+          .and_then(move |_| bot2.answer_query(query_id, None, None))
+          .and_then(|_| Ok(())),
+      );
+    }
+
+    let mut tokens = Vec::new();
+    for kitsu_id in self.db.get_kitsu_ids(user_id) {
+      if let Some(token) = self.db.get_token(user_id, kitsu_id) {
+        tokens.push(token);
+      }
+    }
+
+    let imports = self.imports.clone();
+    let prompts = self.prompts.clone();
+    let undo = self.undo.clone();
+    let callbacks = self.callbacks.clone();
+    let settings = self.settings.clone();
+    let notify = self.notify.clone();
+    let api = self.api.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .db
+        .remove(user_id)
+        .then(move |res| {
+          // what's kept here goes whatever the user store did
+          imports.borrow_mut().remove(&user_id);
+          prompts
+            .borrow_mut()
+            .retain(|_, &mut (_, ref prompt)| prompt.user_id() != user_id);
+          undo.forget(user_id);
+          callbacks.forget(user_id);
+          settings.remove(user_id);
+          notify.forget(user_id);
+          let text = match res {
+            Ok(true) => templates.render("unregister_done", &[]),
+            Ok(false) => templates.render("unregister_kept", &[]),
+            Err(e) => {
+              error!("failed to delete user {}: {:?}", user_id, e);
+              templates.render("unregister_failed", &[])
+            }
+          };
+          let revokes = tokens.into_iter().map(move |token| {
+            api.revoke_token(token).then(|res| {
+              if let Err(ref e) = res {
+                error!("failed to revoke token: {:?}", e);
+              }
+              Ok::<_, Error>(res.is_ok())
+            })
+          });
+          Box::new(join_all(revokes).map(move |revoked| {
+            let mut text = text;
+            if revoked.iter().any(|ok| !ok) {
+              text.push_str(&templates.render("unregister_not_revoked", &[]));
+            }
+            text
+          }))
+        })
+        .and_then(move |text| bot1.replace_message(msg_id, chat_id, has_photo, text, None, None))
+        .and_then(move |_| bot2.answer_query(query_id, None, None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn use_account(
+    &mut self,
+    user_id: i64,
+    chat_id: i64,
+    name: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    if self.db.get_kitsu_id(user_id).is_none() {
+      return self.reply(chat_id, self.non_registered(user_id));
+    }
+    let db = self.db.clone();
+    let bot = self.bot.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .fetch_accounts(user_id)
+        .and_then(move |users| {
+          let found = users
+            .into_iter()
+            .find(|user| {
+              user.id == name || user.attributes.name.to_lowercase() == name.to_lowercase()
+            })
+            .and_then(|user| i64::from_str(&user.id).ok().map(|id| (id, user)));
+          let text = match found {
+            Some((kitsu_id, ref user)) if db.set_active(user_id, kitsu_id) => {
+              templates.render("use_done", &[("name", &user.attributes.name)])
+            }
+            _ => templates.render("use_unknown", &[("name", &name)]),
+          };
+          bot.send_message(chat_id, text, None, None)
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn trending(&mut self, user_id: i64, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let kitsu_id = match self.db.get_kitsu_id(user_id) {
+      Some(kitsu_id) => kitsu_id,
+      None => return self.reply(chat_id, self.non_registered(user_id)),
+    };
+    let bot = self.bot.clone();
+    let settings = self.settings.get(user_id);
+    let heading = self.templates.render("trending_heading", &[]);
+    Box::new(
+      self
+        .title_preference(user_id, kitsu_id)
+        .join(self.api.fetch_trending())
+        .and_then(move |(preference, animes)| {
+          Ok(parse_anime_chart(
+            kitsu_id,
+            &preference,
+            &settings,
+            heading,
+            None,
+            None,
+            None,
+            animes,
+          ))
+        })
+        .and_then(move |(text, buttons)| {
+          bot.send_message(
+            chat_id,
+            text,
+            Some(ParseMode::HTML),
+            Some(bind_keyboard(buttons, user_id)),
+          )
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn season(
+    &mut self,
+    user_id: i64,
+    chat_id: i64,
+    season: Option<Season>,
+    year: Option<i32>,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let kitsu_id = match self.db.get_kitsu_id(user_id) {
+      Some(kitsu_id) => kitsu_id,
+      None => return self.reply(chat_id, self.non_registered(user_id)),
+    };
+    let now = Utc::now();
+    let season = season.unwrap_or(Season::from_month(now.month()));
+    let year = year.unwrap_or(now.year());
+    let bot = self.bot.clone();
+    let settings = self.settings.get(user_id);
+    let heading = self.season_heading(season, year);
+    Box::new(
+      self
+        .title_preference(user_id, kitsu_id)
+        .join(self.api.fetch_season(season, year, 0))
+        .and_then(move |(preference, (prev, next, animes))| {
+          Ok(parse_anime_chart(
+            kitsu_id,
+            &preference,
+            &settings,
+            heading,
+            Some((season, year)),
+            prev,
+            next,
+            animes,
+          ))
+        })
+        .and_then(move |(text, buttons)| {
+          bot.send_message(
+            chat_id,
+            text,
+            Some(ParseMode::HTML),
+            Some(bind_keyboard(buttons, user_id)),
+          )
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn show_settings(&self, user_id: i64, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let (text, buttons) = parse_settings(&self.settings.get(user_id));
+    Box::new(
+      self
+        .bot
+        .send_message(
+          chat_id,
+          text,
+          Some(ParseMode::HTML),
+          Some(bind_keyboard(buttons, user_id)),
+        )
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn set_timezone(
+    &self,
+    user_id: i64,
+    chat_id: i64,
+    name: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let text = match parse_timezone(&name) {
+      Some(zone) => {
+        let change = SettingChange::Timezone(Some(zone.name().to_owned()));
+        let settings = self.settings.update(user_id, &change);
+        self.templates.render(
+          "timezone_set",
+          &[("timezone", zone.name()), ("time", &settings.local_time(&Utc::now()))],
+        )
+      }
+      None => self.templates.render("timezone_unknown", &[("timezone", &name)]),
+    };
+    self.reply(chat_id, text)
+  }
+
+  fn change_setting(
+    &self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    user_id: i64,
+    change: Option<SettingChange>,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let (text, buttons) = match change {
+      Some(change) => {
+        let (text, buttons) = parse_settings(&self.settings.update(user_id, &change));
+        (text, Some(bind_keyboard(buttons, user_id)))
+      }
+      None => (self.templates.render("settings_saved", &[]), None),
+    };
+    let bot = self.bot.clone();
+    Box::new(
+      self
+        .bot
+        .replace_message(msg_id, chat_id, has_photo, text, Some(ParseMode::HTML), buttons)
+        .and_then(move |_| bot.answer_query(query_id, None, None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn mute(
+    &self,
+    msg_id: i64,
+    chat_id: i64,
+    text: String,
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    episode: i64,
+    muted: bool,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    self.notify.set_muted(user_id, &anime_id, muted);
+    let answer = self
+      .templates
+      .render(if muted { "muted" } else { "unmuted" }, &[]);
+    let buttons = parse_episode_buttons(kitsu_id, &anime_id, &entry_id, episode, muted);
+    let bot = self.bot.clone();
+    Box::new(
+      self
+        .bot
+        .clone()
+        .edit_inline_keyboard(msg_id, chat_id, text, None, Some(bind_keyboard(buttons, user_id)))
+        .and_then(move |_| bot.answer_query(query_id, Some(answer), None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  /// Title language picked in the user's settings, or else the one of
+  /// their Kitsu account.
+  fn title_preference(
+    &self,
+    user_id: i64,
+    kitsu_id: i64,
+  ) -> Box<Future<Item = TitleLanguagePreference, Error = Error>> {
+    match self.settings.get(user_id).title_language {
+      Some(preference) => Box::new(done(Ok(preference))),
+      None => self.api.get_title_preference(kitsu_id),
+    }
+  }
+
+  fn season_heading(&self, season: Season, year: i32) -> String {
+    self.templates.render(
+      "season_heading",
+      &[("season", &format!("{:?}", season)), ("year", &year.to_string())],
+    )
+  }
+
+  fn season_page(
+    &self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    owner: i64,
+    kitsu_id: i64,
+    season: Season,
+    year: i32,
+    offset: i64,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let settings = self.settings.get(owner);
+    let heading = self.season_heading(season, year);
+    Box::new(
+      self
+        .title_preference(owner, kitsu_id)
+        .join(self.api.fetch_season(season, year, offset))
+        .and_then(move |(preference, (prev, next, animes))| {
+          Ok(parse_anime_chart(
+            kitsu_id,
+            &preference,
+            &settings,
+            heading,
+            Some((season, year)),
+            prev,
+            next,
+            animes,
+          ))
+        })
+        .and_then(move |(text, buttons)| {
+          bot1.replace_message(
+            msg_id,
+            chat_id,
+            has_photo,
+            text,
+            Some(ParseMode::HTML),
+            Some(bind_keyboard(buttons, owner)),
+          )
+        })
+        .and_then(move |_| bot2.answer_query(query_id, None, None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn plan(
+    &mut self,
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot = self.bot.clone();
+    let templates = self.templates.clone();
+    match self.db.get_token(user_id, kitsu_id) {
+      None => Box::new(
+        bot
+          .answer_query(
+            query_id,
+            Some(self.templates.render("non_registered_alert", &[])),
+            Some(true),
+          )
+          .and_then(|_| Ok(())),
+      ),
+      Some(token) => {
+        let attributes = EntryAttributes {
+          status: Some(EntryStatus::Planned),
+          ..Default::default()
+        };
+        Box::new(
+          self
+            .api
+            .create_entry(token, kitsu_id, anime_id, attributes)
+            .then(move |res| match res {
+              Ok(_) => bot.answer_query(
+                query_id,
+                Some(templates.render("plan_added", &[])),
+                None,
+              ),
+              Err(e) => {
+                info!("failed to add planned entry: {:?}", e);
+                bot.answer_query(
+                  query_id,
+                  Some(templates.render("plan_failed", &[])),
+                  Some(true),
+                )
+              }
+            })
+            .and_then(|_| Ok(())),
+        )
+      }
+    }
+  }
+
+  fn update(&mut self, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let bot = self.bot.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .db
+        .fetch()
+        .and_then(move |users| {
+          bot.send_message(
+            chat_id,
+            templates.render("update_done", &[("count", &users.len().to_string())]),
+            Some(ParseMode::HTML),
+            None,
+          )
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn jobs(&self, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let now = Utc::now();
+    let mut text = String::from("<b>Jobs</b>\n");
+    for (name, running, status) in self.scheduler.jobs() {
+      let ago = parse_duration(now.signed_duration_since(status.last_run.unwrap_or(now)));
+      let last = match status.last_result {
+        _ if running => String::from("running"),
+        None => String::from("never ran"),
+        Some(Ok(result)) => format!("ok {} ago, {}", ago, escape_html(&result)),
+        Some(Err(e)) => format!("failed {} ago, {}", ago, escape_html(&e)),
+      };
+      let next = match status.next_run {
+        None => String::from("never"),
+        Some(next) if next <= now => String::from("due"),
+        Some(next) => format!("in {}", parse_duration(next.signed_duration_since(now))),
+      };
+      text.push_str(&format!("\n<code>{}</code>: {}; next {}", name, last, next));
+    }
+    Box::new(
+      self
+        .bot
+        .send_message(chat_id, text, Some(ParseMode::HTML), None)
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn users(&self, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let mut linked: Vec<(i64, Vec<i64>)> = Vec::new();
+    for user in self.db.users() {
+      match linked.iter().position(|&(id, _)| id == user.telegram_id) {
+        Some(i) => linked[i].1.push(user.kitsu_id),
+        None => linked.push((user.telegram_id, vec![user.kitsu_id])),
+      }
+    }
+    let mut text = format!("<b>{} user(s)</b>\n", linked.len());
+    for (telegram_id, kitsu_ids) in linked {
+      text.push_str(&format!(
+        "\n<code>{}</code>: {}{}",
+        telegram_id,
+        kitsu_ids
+          .iter()
+          .map(|id| id.to_string())
+          .collect::<Vec<_>>()
+          .join(", "),
+        if self.admin.is_banned(telegram_id) { " (banned)" } else { "" }
+      ));
+    }
+    Box::new(
+      self
+        .bot
+        .send_message(chat_id, text, Some(ParseMode::HTML), None)
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn ban(&self, chat_id: i64, target: i64) -> Box<Future<Item = (), Error = Error>> {
+    let name = if self.admin.is_admin(target) {
+      "ban_admin"
+    } else if self.admin.ban(target) {
+      "ban_done"
+    } else {
+      "ban_already"
+    };
+    let text = self
+      .templates
+      .render(name, &[("user_id", &target.to_string())]);
+    self.reply(chat_id, text)
+  }
+
+  fn unban(&self, chat_id: i64, target: i64) -> Box<Future<Item = (), Error = Error>> {
+    let name = if self.admin.unban(target) { "unban_done" } else { "unban_not_banned" };
+    let text = self
+      .templates
+      .render(name, &[("user_id", &target.to_string())]);
+    self.reply(chat_id, text)
+  }
+
+  fn broadcast(&self, chat_id: i64, text: String) -> Box<Future<Item = (), Error = Error>> {
+    let mut targets: Vec<i64> = self
+      .db
+      .users()
+      .iter()
+      .map(|user| user.telegram_id)
+      .filter(|&id| !self.admin.is_banned(id))
+      .collect();
+    targets.sort();
+    targets.dedup();
+    let total = targets.len();
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      stream::iter_ok::<_, Error>(targets)
+        .fold(0, move |sent, target| {
+          bot1
+            .send_message(target, text.clone(), None, None)
+            .then(move |res| match res {
+              Ok(_) => Ok::<_, Error>(sent + 1),
+              Err(e) => {
+                error!("failed to broadcast to {}: {:?}", target, e);
+                Ok(sent)
+              }
+            })
+        })
+        .and_then(move |sent| {
+          bot2.send_message(
+            chat_id,
+            templates.render(
+              "broadcast_done",
+              &[("sent", &sent.to_string()), ("total", &total.to_string())],
+            ),
+            None,
+            None,
+          )
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn maintenance(&self, chat_id: i64, on: bool) -> Box<Future<Item = (), Error = Error>> {
+    self.admin.set_maintenance(on);
+    let name = if on { "maintenance_on" } else { "maintenance_off" };
+    self.reply(chat_id, self.templates.render(name, &[]))
+  }
+
+  fn rotate_key(&self, chat_id: i64) -> Box<Future<Item = (), Error = Error>> {
+    let bot = self.bot.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .db
+        .rotate()
+        .and_then(move |(rotated, failed, saved)| {
+          let mut text = templates.render("rotate_done", &[("rotated", &rotated.to_string())]);
+          if failed > 0 {
+            text.push_str(&templates.render("rotate_failed", &[("failed", &failed.to_string())]));
+          }
+          if !saved {
+            text.push_str(&templates.render("rotate_unsaved", &[]));
+          }
+          bot.send_message(chat_id, text, None, None)
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn offset(
+    &self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    owner: i64,
+    kitsu_id: i64,
+    offset: i64,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let settings = self.settings.get(owner);
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .title_preference(owner, kitsu_id)
+        .join(self.api.fetch_anime(
+          self.db.get_token(owner, kitsu_id),
+          kitsu_id,
+          offset,
+          settings.page_size,
+        ))
+        .and_then(move |(preference, (prev, next, entries, animes))| {
+          Ok(parse_anime_list(
+            kitsu_id,
+            &preference,
+            &settings,
+            &templates,
+            prev,
+            next,
+            entries,
+            animes,
+          ))
+        })
+        .and_then(move |(text, buttons)| {
+          bot1.replace_message(
+            msg_id,
+            chat_id,
+            has_photo,
+            text,
+            Some(ParseMode::HTML),
+            Some(bind_keyboard(buttons, owner)),
+          )
+        })
+        .and_then(move |_| bot2.answer_query(query_id, None, None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn detail(
+    &self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    owner: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let settings = self.settings.get(owner);
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .title_preference(owner, kitsu_id)
+        .join3(
+          self.api.get_anime(self.db.get_token(owner, kitsu_id), kitsu_id, &anime_id),
+          self.api.get_genres(&anime_id),
+        )
+        .and_then(move |(preference, pair, genres)| {
+          Ok(parse_anime_detail(kitsu_id, &preference, &settings, &templates, pair, genres))
+        })
+        .and_then(move |(poster, text, buttons)| match poster {
+          Some(poster) => {
+            let bot = bot1.clone();
+            let buttons = bind_keyboard(buttons, owner);
+            let fallback = (text.clone(), buttons.clone());
+            Box::new(
+              bot1
+                .send_photo(chat_id, poster, Some(text), Some(ParseMode::HTML), Some(buttons))
+                .then(move |res| match res {
+                  // the old message only goes once the new one is there
+                  Ok(msg) => Box::new(bot.delete_message(chat_id, msg_id).then(move |res| {
+                    if let Err(e) = res {
+                      error!("failed to delete message {} in {}: {:?}", msg_id, chat_id, e);
+                    }
+                    Ok(msg)
+                  })) as Box<Future<Item = Message, Error = Error>>,
+                  Err(e) => {
+                    error!("failed to send the poster, sending text instead: {:?}", e);
+                    let (text, buttons) = fallback;
+                    bot.replace_message(
+                      msg_id,
+                      chat_id,
+                      has_photo,
+                      text,
+                      Some(ParseMode::HTML),
+                      Some(buttons),
+                    )
+                  }
+                }),
+            ) as Box<Future<Item = Message, Error = Error>>
+          }
+          None => bot1.replace_message(
+            msg_id,
+            chat_id,
+            has_photo,
+            text,
+            Some(ParseMode::HTML),
+            Some(bind_keyboard(buttons, owner)),
+          ),
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.caption.or(msg.text), msg.chat);
+          Ok(())
+        })
+        .and_then(move |_| bot2.answer_query(query_id, None, None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn progress(
+    &mut self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    progress: i64,
+    entry_id: String,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot = self.bot.clone();
+    let token = self.db.get_token(user_id, kitsu_id);
+    match token {
+      None => Box::new(
+        bot
+          .answer_query(
+            query_id,
+            Some(self.templates.render("non_registered_alert", &[])),
+            Some(true),
+          )
+          .and_then(|_| Ok(())),
+      ),
+      Some(token) => Box::new(
+        change_progress(
+          &self.api,
+          &self.undo,
+          &self.templates,
+          token,
+          user_id,
+          kitsu_id,
+          anime_id,
+          entry_id,
+          progress,
+        )
+          .and_then(move |(text, buttons)| {
+            bot.replace_message(
+              msg_id,
+              chat_id,
+              has_photo,
+              text,
+              Some(ParseMode::HTML),
+              Some(bind_keyboard(buttons, user_id)),
+            )
+          })
+          .and_then(|msg| {
+            info!("send message: {:?} in {:?}", msg.text, msg.chat);
+            Ok(())
+          }),
+      ),
+    }
+  }
+
+  fn prompt_progress(
+    &self,
+    chat_id: i64,
+    user_id: i64,
+    first_name: String,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let prompts = self.prompts.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .title_preference(user_id, kitsu_id)
+        .join(self.api.get_anime(self.db.get_token(user_id, kitsu_id), kitsu_id, &anime_id))
+        .and_then(move |(preference, pair)| {
+          let (title, episode_count) = match pair.and_then(|(_, anime)| anime.attributes) {
+            Some(attr) => (attr.title(&preference).to_owned(), attr.episode_count),
+            None => (templates.render("anime_unknown_title", &[]), None),
+          };
+          // the mention is what keeps the reply box to this user in groups
+          let mention = mention(user_id, &first_name);
+          let title = escape_html(&title);
+          let text = match episode_count {
+            Some(count) => templates.render(
+              "progress_prompt",
+              &[("mention", &mention), ("title", &title), ("episode_count", &count.to_string())],
+            ),
+            None => templates.render(
+              "progress_prompt_open",
+              &[("mention", &mention), ("title", &title)],
+            ),
+          };
+          bot1.send_prompt(chat_id, text).map(move |msg| {
+            remember_prompt(
+              &prompts,
+              (chat_id, msg.message_id.unwrap_or(0)),
+              Prompt::Progress {
+                user_id,
+                kitsu_id,
+                anime_id,
+                entry_id,
+                episode_count,
+              },
+            );
+          })
+        })
+        .and_then(move |_| bot2.answer_query(query_id, None, None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn answer(
+    &mut self,
+    user_id: i64,
+    chat_id: i64,
+    prompt: Prompt,
+    text: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    match prompt {
+      Prompt::Progress {
+        kitsu_id,
+        anime_id,
+        entry_id,
+        episode_count,
+        ..
+      } => {
+        let templates = &self.templates;
+        let progress = i64::from_str(text.trim())
+          .map_err(|_| templates.render("progress_not_a_number", &[]))
+          .and_then(|progress| check_progress(templates, progress, episode_count));
+        match progress {
+          Err(reason) => self.reply(chat_id, reason),
+          Ok(progress) => {
+            self.save_progress(user_id, chat_id, kitsu_id, anime_id, entry_id, progress)
+          }
+        }
+      }
+      Prompt::Notes {
+        kitsu_id,
+        anime_id,
+        entry_id,
+        ..
+      } => {
+        let token = match self.db.get_token(user_id, kitsu_id) {
+          Some(token) => token,
+          None => return self.reply(chat_id, self.non_registered(user_id)),
+        };
+        let bot = self.bot.clone();
+        let saved = self.templates.render("notes_saved", &[]);
+        let attributes = EntryAttributes {
+          notes: Some(text.trim().to_owned()),
+          ..Default::default()
+        };
+        Box::new(
+          self
+            .api
+            .update_entry(token, entry_id, attributes)
+            .and_then(move |_| {
+              let (text, buttons) = parse_entry_update(kitsu_id, &anime_id, saved, None);
+              bot.send_message(chat_id, text, None, Some(bind_keyboard(buttons, user_id)))
+            })
+            .and_then(|msg| {
+              info!("send message: {:?} in {:?}", msg.text, msg.chat);
+              Ok(())
+            }),
+        )
+      }
+    }
+  }
+
+  fn prompt_notes(
+    &self,
+    chat_id: i64,
+    user_id: i64,
+    first_name: String,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot = self.bot.clone();
+    let prompts = self.prompts.clone();
+    Box::new(
+      self
+        .bot
+        .send_prompt(
+          chat_id,
+          self.templates.render(
+            "notes_prompt",
+            &[("mention", &mention(user_id, &first_name))],
+          ),
+        )
+        .map(move |msg| {
+          remember_prompt(
+            &prompts,
+            (chat_id, msg.message_id.unwrap_or(0)),
+            Prompt::Notes {
+              user_id,
+              kitsu_id,
+              anime_id,
+              entry_id,
+            },
+          );
+        })
+        .and_then(move |_| bot.answer_query(query_id, None, None))
+        .and_then(|_| Ok(())),
+    )
+  }
+
+  fn private(
+    &mut self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    private: bool,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let templates = self.templates.clone();
+    match self.db.get_token(user_id, kitsu_id) {
+      None => Box::new(
+        bot1
+          .answer_query(
+            query_id,
+            Some(self.templates.render("non_registered_alert", &[])),
+            Some(true),
+          )
+          .and_then(|_| Ok(())),
+      ),
+      Some(token) => {
+        let attributes = EntryAttributes {
+          private: Some(private),
+          ..Default::default()
+        };
+        Box::new(
+          self
+            .api
+            .update_entry(token, entry_id, attributes)
+            .and_then(move |_| {
+              let text = templates.render(
+                if private { "entry_private" } else { "entry_public" },
+                &[],
+              );
+              let (text, buttons) = parse_entry_update(kitsu_id, &anime_id, text, None);
+              bot1.replace_message(
+                msg_id,
+                chat_id,
+                has_photo,
+                text,
+                None,
+                Some(bind_keyboard(buttons, user_id)),
+              )
+            })
+            .and_then(move |_| bot2.answer_query(query_id, None, None))
+            .and_then(|_| Ok(())),
+        )
+      }
+    }
+  }
+
+  fn progress_by_title(
+    &mut self,
+    user_id: i64,
+    chat_id: i64,
+    title: String,
+    progress: i64,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let kitsu_id = match self.db.get_kitsu_id(user_id) {
+      Some(kitsu_id) => kitsu_id,
+      None => return self.reply(chat_id, self.non_registered(user_id)),
+    };
+    let token = match self.db.get_token(user_id, kitsu_id) {
+      Some(token) => token,
+      None => return self.reply(chat_id, self.non_registered(user_id)),
+    };
+    let api = self.api.clone();
+    let undo = self.undo.clone();
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let templates = self.templates.clone();
+    Box::new(
+      self
+        .api
+        .fetch_library(Some(token.clone()), kitsu_id)
+        .and_then(move |(entries, animes, _)| {
+          let result = find_entry(&title, entries, animes).map_err(|titles| {
+            if titles.is_empty() {
+              templates.render("progress_no_match", &[("title", &title)])
+            } else {
+              templates.render("progress_ambiguous", &[("titles", &titles.join("\n"))])
+            }
+          });
+          match result.and_then(|(entry, anime)| {
+            let episode_count = anime.attributes.as_ref().and_then(|a| a.episode_count);
+            check_progress(&templates, progress, episode_count)
+              .map(|progress| (entry, anime, progress))
+          }) {
+            Err(reason) => bot1.send_message(chat_id, reason, None, None),
+            Ok((entry, anime, progress)) => Box::new(
+              change_progress(
+                &api,
+                &undo,
+                &templates,
+                token,
+                user_id,
+                kitsu_id,
+                anime.id,
+                entry.id,
+                progress,
+              ).and_then(move |(text, buttons)| {
+                bot2.send_message(
+                  chat_id,
+                  text,
+                  Some(ParseMode::HTML),
+                  Some(bind_keyboard(buttons, user_id)),
+                )
+              }),
+            ) as Box<Future<Item = Message, Error = Error>>,
+          }
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn save_progress(
+    &mut self,
+    user_id: i64,
+    chat_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    progress: i64,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let token = match self.db.get_token(user_id, kitsu_id) {
+      Some(token) => token,
+      None => return self.reply(chat_id, self.non_registered(user_id)),
+    };
+    let bot = self.bot.clone();
+    Box::new(
+      change_progress(
+        &self.api,
+        &self.undo,
+        &self.templates,
+        token,
+        user_id,
+        kitsu_id,
+        anime_id,
+        entry_id,
+        progress,
+      )
+        .and_then(move |(text, buttons)| {
+          bot.send_message(
+            chat_id,
+            text,
+            Some(ParseMode::HTML),
+            Some(bind_keyboard(buttons, user_id)),
+          )
+        })
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+
+  fn rewatch(
+    &mut self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let templates = self.templates.clone();
+    match self.db.get_token(user_id, kitsu_id) {
+      None => Box::new(
+        bot1
+          .answer_query(
+            query_id,
+            Some(self.templates.render("non_registered_alert", &[])),
+            Some(true),
+          )
+          .and_then(|_| Ok(())),
+      ),
+      Some(token) => Box::new(
+        change_entry(
+          &self.api,
+          &self.undo,
+          token,
+          user_id,
+          kitsu_id,
+          anime_id.clone(),
+          entry_id,
+          |_, _| EntryAttributes {
+            progress: Some(0),
+            status: Some(EntryStatus::Current),
+            reconsuming: Some(true),
+            ..Default::default()
+          },
+        )
+          .and_then(move |change_id| {
+            let (text, buttons) = parse_entry_update(
+              kitsu_id,
+              &anime_id,
+              templates.render("rewatch_started", &[]),
+              Some(change_id),
+            );
+            bot1.replace_message(
+              msg_id,
+              chat_id,
+              has_photo,
+              text,
+              None,
+              Some(bind_keyboard(buttons, user_id)),
+            )
+          })
+          .and_then(move |_| bot2.answer_query(query_id, None, None))
+          .and_then(|_| Ok(())),
+      ),
+    }
+  }
+
+  fn set_status(
+    &mut self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    status: EntryStatus,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let templates = self.templates.clone();
+    match self.db.get_token(user_id, kitsu_id) {
+      None => Box::new(
+        bot1
+          .answer_query(
+            query_id,
+            Some(self.templates.render("non_registered_alert", &[])),
+            Some(true),
+          )
+          .and_then(|_| Ok(())),
+      ),
+      Some(token) => Box::new(
+        change_entry(
+          &self.api,
+          &self.undo,
+          token,
+          user_id,
+          kitsu_id,
+          anime_id.clone(),
+          entry_id,
+          {
+            let status = status.clone();
+            move |_, _| EntryAttributes {
+              status: Some(status),
+              ..Default::default()
+            }
+          },
+        )
+          .and_then(move |change_id| {
+            let (text, buttons) = parse_entry_update(
+              kitsu_id,
+              &anime_id,
+              templates.render("status_changed", &[("status", &format!("{:?}", status))]),
+              Some(change_id),
+            );
+            bot1.replace_message(
+              msg_id,
+              chat_id,
+              has_photo,
+              text,
+              None,
+              Some(bind_keyboard(buttons, user_id)),
+            )
+          })
+          .and_then(move |_| bot2.answer_query(query_id, None, None))
+          .and_then(|_| Ok(())),
+      ),
+    }
+  }
+
+  fn undo(
+    &mut self,
+    msg_id: i64,
+    chat_id: i64,
+    has_photo: bool,
+    user_id: i64,
+    kitsu_id: i64,
+    change_id: u64,
+    query_id: String,
+  ) -> Box<Future<Item = (), Error = Error>> {
+    let bot1 = self.bot.clone();
+    let bot2 = self.bot.clone();
+    let token = match self.db.get_token(user_id, kitsu_id) {
+      Some(token) => token,
+      None => {
+        return Box::new(
+          bot1
+            .answer_query(
+              query_id,
+              Some(self.templates.render("non_registered_alert", &[])),
+              Some(true),
+            )
+            .and_then(|_| Ok(())),
+        )
+      }
+    };
+    match self.undo.take(change_id, user_id, kitsu_id) {
+      None => {
+        let (text, buttons) = parse_undo(kitsu_id, None);
+        Box::new(
+          bot1
+            .answer_query(query_id, None, None)
+            .and_then(move |_| {
+              bot2.replace_message(
+                msg_id,
+                chat_id,
+                has_photo,
+                text,
+                None,
+                Some(bind_keyboard(buttons, user_id)),
+              )
+            })
+            .and_then(|_| Ok(())),
+        )
+      }
+      Some(change) => {
+        let attributes = EntryAttributes {
+          progress: change.progress,
+          status: change.status.clone(),
+          reconsuming: change.reconsuming,
+          reconsume_count: change.reconsume_count,
+          ..Default::default()
+        };
+        let undo = self.undo.clone();
+        Box::new(
+          self
+            .api
+            .update_entry(token, change.entry_id.clone(), attributes)
+            .then(move |res| match res {
+              Ok(_) => Ok(change),
+              // still undoable if Kitsu didn't take it
+              Err(e) => {
+                undo.restore(change_id, change);
+                Err(e)
+              }
+            })
+            .and_then(move |change| {
+              let (text, buttons) = parse_undo(kitsu_id, Some(change));
+              bot1.replace_message(
+                msg_id,
+                chat_id,
+                has_photo,
+                text,
+                None,
+                Some(bind_keyboard(buttons, user_id)),
+              )
+            })
+            .and_then(move |_| bot2.answer_query(query_id, None, None))
+            .and_then(|_| Ok(())),
+        )
+      }
+    }
+  }
+
+  fn reply(&self, chat_id: i64, text: String) -> Box<Future<Item = (), Error = Error>> {
+    Box::new(
+      self
+        .bot
+        .send_message(chat_id, text, None, None)
+        .and_then(|msg| {
+          info!("send message: {:?} in {:?}", msg.text, msg.chat);
+          Ok(())
+        }),
+    )
+  }
+}
+
+fn is_stale(asked_at: DateTime<Utc>) -> bool {
+  Utc::now().signed_duration_since(asked_at) > Duration::minutes(PROMPT_TTL_MINUTES)
+}
+
+/// Keeps the prompt until it's answered, dropping the ones nobody answered
+/// in time.
+fn remember_prompt(prompts: &Prompts, key: (i64, i64), prompt: Prompt) {
+  let mut prompts = prompts.borrow_mut();
+  prompts.retain(|_, &mut (asked_at, _)| !is_stale(asked_at));
+  prompts.insert(key, (Utc::now(), prompt));
+}
+
+/// Changes an entry, remembering its previous state so the change can be
+/// undone. `change` is given the previous attributes and the episode count.
+fn change_entry<F>(
+  api: &Api,
+  undo: &UndoLog,
+  token: String,
+  user_id: i64,
+  kitsu_id: i64,
+  anime_id: String,
+  entry_id: String,
+  change: F,
+) -> Box<Future<Item = u64, Error = Error>>
+where
+  F: FnOnce(&EntryAttributes, Option<u32>) -> EntryAttributes + 'static,
+{
+  let api = api.clone();
+  let undo = undo.clone();
+  Box::new(api.get_entry(Some(token.clone()), &entry_id).and_then(move |(entry, anime)| {
+    let previous = entry.attributes.unwrap_or_default();
+    let episode_count = anime
+      .and_then(|anime| anime.attributes)
+      .and_then(|attr| attr.episode_count);
+    let attributes = change(&previous, episode_count);
+    api
+      .update_entry(token, entry_id.clone(), attributes)
+      .map(move |_| {
+        undo.record(Change {
+          user_id,
+          kitsu_id,
+          anime_id,
+          entry_id,
+          progress: previous.progress,
+          status: previous.status,
+          reconsuming: previous.reconsuming,
+          reconsume_count: previous.reconsume_count,
+          updated_at: previous.updated_at,
+          changed_at: Utc::now(),
+        })
+      })
+  }))
+}
+
+/// Changes the progress of an entry. Resolves to the message telling about
+/// the update.
+fn change_progress(
+  api: &Api,
+  undo: &UndoLog,
+  templates: &Templates,
+  token: String,
+  user_id: i64,
+  kitsu_id: i64,
+  anime_id: String,
+  entry_id: String,
+  progress: i64,
+) -> Box<Future<Item = (String, Keyboard), Error = Error>> {
+  let templates = templates.clone();
+  Box::new(
+    change_entry(
+      api,
+      undo,
+      token,
+      user_id,
+      kitsu_id,
+      anime_id.clone(),
+      entry_id,
+      move |previous, episode_count| {
+        let finished = episode_count.map_or(false, |count| progress >= count as i64);
+        if previous.reconsuming == Some(true) && finished {
+          // a finished rewatch counts as another watch, not a new completion
+          EntryAttributes {
+            progress: Some(progress),
+            status: Some(EntryStatus::Completed),
+            reconsuming: Some(false),
+            reconsume_count: Some(previous.reconsume_count.unwrap_or(0) + 1),
+            ..Default::default()
+          }
+        } else {
+          EntryAttributes {
+            progress: Some(progress),
+            ..Default::default()
+          }
+        }
+      },
+    ).map(move |change_id| {
+      parse_progress_update(kitsu_id, &templates, &anime_id, progress, Some(change_id))
+    }),
+  )
 }
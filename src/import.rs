@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use types::kitsu::{Entry, EntryAttributes, EntryStatus};
+use types::telegram::Document;
+use utils::escape_html;
+
+/// How long an import preview can be started after it was sent.
+pub const IMPORT_TTL_MINUTES: i64 = 30;
+
+#[derive(Debug)]
+pub struct MalEntry {
+  pub mal_id: String,
+  pub title: String,
+  pub status: EntryStatus,
+  pub progress: i64,
+  pub score: i64,
+}
+
+impl MalEntry {
+  pub fn attributes(&self) -> EntryAttributes {
+    EntryAttributes {
+      status: Some(self.status.clone()),
+      progress: Some(self.progress),
+      // mal rates out of 10, kitsu out of 20
+      rating_twenty: if self.score > 0 { Some(self.score * 2) } else { None },
+      ..Default::default()
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ImportAction {
+  Create,
+  Update(String),
+}
+
+#[derive(Debug)]
+pub struct ImportItem {
+  pub entry: MalEntry,
+  pub anime_id: String,
+  pub action: ImportAction,
+}
+
+#[derive(Debug)]
+pub struct ImportPlan {
+  pub kitsu_id: i64,
+  pub items: Vec<ImportItem>,
+  pub unchanged: usize,
+  pub unmatched: Vec<MalEntry>,
+  pub created_at: DateTime<Utc>,
+}
+
+impl ImportPlan {
+  pub fn is_expired(&self) -> bool {
+    Utc::now().signed_duration_since(self.created_at) > Duration::minutes(IMPORT_TTL_MINUTES)
+  }
+}
+
+/// Whether the document looks like a MyAnimeList XML export, so other
+/// files sent to the bot don't start an import.
+pub fn is_mal_export(document: &Document) -> bool {
+  let xml_name = document
+    .file_name
+    .as_ref()
+    .map_or(false, |name| name.to_lowercase().ends_with(".xml"));
+  let xml_type = document
+    .mime_type
+    .as_ref()
+    .map_or(false, |mime| mime == "application/xml" || mime == "text/xml");
+  xml_name || xml_type
+}
+
+fn tag_value<'a>(block: &'a str, tag: &str) -> Option<&'a str> {
+  let open = format!("<{}>", tag);
+  let close = format!("</{}>", tag);
+  block.find(&open).and_then(|start| {
+    let rest = &block[start + open.len()..];
+    rest.find(&close).map(|end| {
+      let value = rest[..end].trim();
+      if value.starts_with("<![CDATA[") && value.ends_with("]]>") {
+        &value[9..value.len() - 3]
+      } else {
+        value
+      }
+    })
+  })
+}
+
+pub fn parse_mal_status(status: &str) -> Option<EntryStatus> {
+  // older exports use numbers instead of names
+  match status {
+    "Watching" | "1" => Some(EntryStatus::Current),
+    "Completed" | "2" => Some(EntryStatus::Completed),
+    "On-Hold" | "3" => Some(EntryStatus::OnHold),
+    "Dropped" | "4" => Some(EntryStatus::Dropped),
+    "Plan to Watch" | "6" => Some(EntryStatus::Planned),
+    _ => None,
+  }
+}
+
+/// Extracts anime entries from a MyAnimeList XML export. Entries that
+/// can't be understood are left out.
+pub fn parse_mal_xml(xml: &str) -> Vec<MalEntry> {
+  xml
+    .split("<anime>")
+    .skip(1)
+    .filter_map(|block| {
+      let block = block.split("</anime>").next().unwrap_or("");
+      match (
+        tag_value(block, "series_animedb_id"),
+        tag_value(block, "my_status").and_then(parse_mal_status),
+      ) {
+        (Some(mal_id), Some(status)) => Some(MalEntry {
+          status,
+          mal_id: mal_id.to_owned(),
+          title: tag_value(block, "series_title").unwrap_or("").to_owned(),
+          progress: tag_value(block, "my_watched_episodes")
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(0),
+          score: tag_value(block, "my_score")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0),
+        }),
+        _ => None,
+      }
+    })
+    .collect()
+}
+
+/// Decides what to do with every entry of the export, given the Kitsu ids
+/// its MyAnimeList ids map to and the user's current library.
+pub fn plan(
+  kitsu_id: i64,
+  entries: Vec<MalEntry>,
+  mappings: Vec<(String, String)>,
+  library: Vec<Entry>,
+) -> ImportPlan {
+  let mappings: HashMap<String, String> = mappings.into_iter().collect();
+  let library: HashMap<String, Entry> = library
+    .into_iter()
+    .filter_map(|entry| {
+      let anime_id = entry
+        .relationships
+        .as_ref()
+        .and_then(|r| r.anime.as_ref())
+        .and_then(|a| a.data.as_ref())
+        .map(|a| a.id.clone());
+      anime_id.map(|id| (id, entry))
+    })
+    .collect();
+
+  let mut items = Vec::new();
+  let mut unchanged = 0;
+  let mut unmatched = Vec::new();
+  for entry in entries {
+    let anime_id = match mappings.get(&entry.mal_id) {
+      Some(anime_id) => anime_id.clone(),
+      None => {
+        unmatched.push(entry);
+        continue;
+      }
+    };
+    let action = match library.get(&anime_id) {
+      None => ImportAction::Create,
+      Some(existing) => {
+        let same = existing.attributes.as_ref().map_or(false, |attr| {
+          attr.status.as_ref() == Some(&entry.status) && attr.progress == Some(entry.progress)
+        });
+        if same {
+          unchanged += 1;
+          continue;
+        }
+        ImportAction::Update(existing.id.clone())
+      }
+    };
+    items.push(ImportItem { entry, anime_id, action });
+  }
+
+  ImportPlan {
+    kitsu_id,
+    items,
+    unchanged,
+    unmatched,
+    created_at: Utc::now(),
+  }
+}
+
+pub fn summary(plan: &ImportPlan) -> String {
+  let created = plan
+    .items
+    .iter()
+    .filter(|item| match item.action {
+      ImportAction::Create => true,
+      _ => false,
+    })
+    .count();
+  let mut text = format!(
+    "<b>Import Preview</b>\n\
+     <b>New</b>: {}\n\
+     <b>Updated</b>: {}\n\
+     <b>Unchanged</b>: {}\n\
+     <b>Not found on Kitsu</b>: {}",
+    created,
+    plan.items.len() - created,
+    plan.unchanged,
+    plan.unmatched.len()
+  );
+  for entry in plan.unmatched.iter().take(10) {
+    text.push_str(&format!("\n- {} (#{})", escape_html(&entry.title), entry.mal_id));
+  }
+  if plan.unmatched.len() > 10 {
+    text.push_str(&format!("\n- and {} more", plan.unmatched.len() - 10));
+  }
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::from_str;
+
+  use super::{parse_mal_xml, plan, ImportAction, MalEntry};
+  use types::kitsu::{Entry, EntryStatus};
+
+  fn mal(mal_id: &str, status: EntryStatus, progress: i64) -> MalEntry {
+    MalEntry {
+      status,
+      progress,
+      mal_id: mal_id.to_owned(),
+      title: format!("#{}", mal_id),
+      score: 0,
+    }
+  }
+
+  fn library_entry(id: &str, anime_id: &str, status: &str, progress: i64) -> Entry {
+    from_str(&format!(
+      r#"{{
+        "id": "{}",
+        "type": "libraryEntries",
+        "attributes": {{ "status": "{}", "progress": {} }},
+        "relationships": {{ "anime": {{ "data": {{ "id": "{}", "type": "anime" }} }} }}
+      }}"#,
+      id, status, progress, anime_id
+    )).unwrap()
+  }
+
+  #[test]
+  fn plan_sorts_entries_by_what_changes() {
+    let entries = vec![
+      mal("1", EntryStatus::Current, 3),
+      mal("2", EntryStatus::Completed, 12),
+      mal("3", EntryStatus::Current, 5),
+      mal("4", EntryStatus::Planned, 0),
+    ];
+    let mappings = vec![
+      (String::from("1"), String::from("100")),
+      (String::from("2"), String::from("200")),
+      (String::from("3"), String::from("300")),
+    ];
+    let library = vec![
+      library_entry("20", "200", "current", 11),
+      library_entry("30", "300", "current", 5),
+    ];
+    let plan = plan(42, entries, mappings, library);
+
+    assert_eq!(plan.kitsu_id, 42);
+    assert_eq!(plan.items.len(), 2);
+    assert_eq!(plan.items[0].anime_id, "100");
+    match plan.items[0].action {
+      ImportAction::Create => (),
+      ref action => panic!("expected a new entry, got {:?}", action),
+    }
+    assert_eq!(plan.items[1].anime_id, "200");
+    match plan.items[1].action {
+      ImportAction::Update(ref entry_id) => assert_eq!(entry_id, "20"),
+      ref action => panic!("expected an update, got {:?}", action),
+    }
+    assert_eq!(plan.unchanged, 1);
+    assert_eq!(plan.unmatched.len(), 1);
+    assert_eq!(plan.unmatched[0].mal_id, "4");
+    assert!(!plan.is_expired());
+  }
+
+  #[test]
+  fn parses_numeric_statuses_and_skips_unknown_ones() {
+    let xml = "<myanimelist>\
+               <anime><series_animedb_id>1</series_animedb_id><my_status>3</my_status>\
+               <my_watched_episodes>4</my_watched_episodes></anime>\
+               <anime><series_animedb_id>2</series_animedb_id><my_status>5</my_status></anime>\
+               <anime><my_status>Watching</my_status></anime>\
+               </myanimelist>";
+    let entries = parse_mal_xml(xml);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].mal_id, "1");
+    assert_eq!(entries[0].status, EntryStatus::OnHold);
+    assert_eq!(entries[0].progress, 4);
+    assert_eq!(entries[0].score, 0);
+  }
+
+  #[test]
+  fn scores_are_doubled_and_zero_is_unrated() {
+    let mut entry = mal("1", EntryStatus::Completed, 12);
+    entry.score = 8;
+    assert_eq!(entry.attributes().rating_twenty, Some(16));
+    entry.score = 0;
+    assert_eq!(entry.attributes().rating_twenty, None);
+  }
+}
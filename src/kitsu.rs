@@ -1,18 +1,24 @@
 use std::str::FromStr;
 
-use url::Url;
+use url::{form_urlencoded, Url};
 
-use futures::{future, Future, Stream};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use futures::{Future, Stream};
+use futures::future::{loop_fn, Loop};
 
 use hyper::mime::Mime;
 use hyper::{Method, Request, Uri};
 use hyper::header::{Authorization, Bearer, ContentLength, ContentType};
 
+use serde::de::DeserializeOwned;
 use serde_json::{from_slice, to_string};
 
-use types::Client;
+use types::{Client, Season};
 use error::{Error, KitsuError};
-use types::kitsu::{Anime, Entry, EntryAttributes, Json, Relationships, Type};
+use types::kitsu::{Anime, Document, Entry, EntryAttributes, Episode, Errors, Genre, Included,
+                   LibraryEvent, Mapping, Relationships, TitleLanguagePreference, ToOne, Type,
+                   User, pair_anime};
 
 #[derive(Clone)]
 pub struct Api {
@@ -28,25 +34,25 @@ impl Api {
     }
   }
 
-  fn request(&self, req: Request) -> Box<Future<Item = Json, Error = Error>> {
+  fn request<T>(&self, req: Request) -> Box<Future<Item = Document<T>, Error = Error>>
+  where
+    T: DeserializeOwned + 'static,
+  {
     Box::new(self.client.request(req).from_err::<Error>().and_then(
       |res| {
         res
           .body()
           .from_err::<Error>()
           .concat2()
-          .and_then(|chunks| {
-            future::result::<Json, Error>(from_slice(&chunks).map_err(|e| e.into()))
-          })
-          .and_then(|res| match res {
-            Json::Error { errors } => {
+          .and_then(|chunks| match from_slice::<Errors>(&chunks) {
+            Ok(Errors { errors }) => {
               let mut description = String::new();
               for e in errors {
                 description.push_str(&format!("{}: {}", e.title, e.detail))
               }
               Err(Error::Kitsu(KitsuError { description }))
             }
-            _ => Ok(res),
+            Err(_) => from_slice::<Document<T>>(&chunks).map_err(|e| e.into()),
           })
       },
     ))
@@ -54,22 +60,24 @@ impl Api {
 
   pub fn fetch_anime(
     &self,
+    token: Option<String>,
     user_id: i64,
     offset: i64,
+    limit: u32,
   ) -> Box<Future<Item = (Option<String>, Option<String>, Vec<Entry>, Vec<Anime>), Error = Error>> {
     let mut endpoint = self.base.join("library-entries").unwrap();
 
     let url = endpoint
       .query_pairs_mut()
       .append_pair("include", "anime")
-      .append_pair("page[limit]", "4")
+      .append_pair("page[limit]", &limit.to_string())
       .append_pair("page[offset]", &offset.to_string())
       .append_pair("filter[user_id]", &user_id.to_string())
       .append_pair("filter[status]", "current,planned")
       .append_pair("fields[libraryEntries]", "progress,status,updatedAt,anime")
       .append_pair(
         "fields[anime]",
-        "canonicalTitle,titles,episodeCount,slug,subtype",
+        "canonicalTitle,titles,abbreviatedTitles,episodeCount,slug,subtype",
       )
       .finish()
       .as_str();
@@ -79,26 +87,354 @@ impl Api {
     req.headers_mut().set(ContentType(
       Mime::from_str("application/vnd.api+json").unwrap(),
     ));
+    authorize(&mut req, token);
+
+    Box::new(self.request::<Vec<Entry>>(req).map(|doc| {
+      let (prev, next) = doc.prev_next();
+      let (entries, animes) = doc.included_anime();
+      (prev, next, entries, animes)
+    }))
+  }
+
+  /// Fetches the user's progress, status and rating changes, newest first.
+  pub fn fetch_library_events(
+    &self,
+    token: Option<String>,
+    user_id: i64,
+    offset: i64,
+    limit: u32,
+  ) -> Box<
+    Future<Item = (Option<String>, Option<String>, Vec<LibraryEvent>, Vec<Anime>), Error = Error>,
+  > {
+    let mut endpoint = self.base.join("library-events").unwrap();
 
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("include", "anime")
+      .append_pair("sort", "-createdAt")
+      .append_pair("page[limit]", &limit.to_string())
+      .append_pair("page[offset]", &offset.to_string())
+      .append_pair("filter[userId]", &user_id.to_string())
+      .append_pair("filter[kind]", "progressed,updated,rated")
+      .append_pair("fields[libraryEvents]", "kind,changedData,createdAt,anime")
+      .append_pair("fields[anime]", "canonicalTitle,titles,episodeCount")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+    authorize(&mut req, token);
+
+    Box::new(self.request::<Vec<LibraryEvent>>(req).map(|doc| {
+      let (prev, next) = doc.prev_next();
+      let (events, animes) = doc.included_anime();
+      (prev, next, events, animes)
+    }))
+  }
+
+  /// Fetches the user's library events that happened after `since`.
+  pub fn fetch_library_events_since(
+    &self,
+    token: Option<String>,
+    user_id: i64,
+    since: DateTime<Utc>,
+  ) -> Box<Future<Item = Vec<LibraryEvent>, Error = Error>> {
+    let api = self.clone();
+    Box::new(loop_fn(
+      Vec::new(),
+      move |mut events: Vec<LibraryEvent>| {
+        api
+          .fetch_library_events(token.clone(), user_id, events.len() as i64, 20)
+          .and_then(move |(_, next, page, _)| {
+            let done = next.is_none() ||
+              page.iter().any(|event| event.attributes.created_at <= since);
+            events.extend(page.into_iter().filter(|event| event.attributes.created_at > since));
+            if done {
+              Ok(Loop::Break(events))
+            } else {
+              Ok(Loop::Continue(events))
+            }
+          })
+      },
+    ))
+  }
+
+  fn fetch_watching_page(
+    &self,
+    token: Option<String>,
+    user_id: i64,
+    offset: usize,
+  ) -> Box<Future<Item = (Option<String>, Vec<Entry>, Vec<Anime>), Error = Error>> {
+    let mut endpoint = self.base.join("library-entries").unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("include", "anime")
+      .append_pair("page[limit]", "500")
+      .append_pair("page[offset]", &offset.to_string())
+      .append_pair("filter[user_id]", &user_id.to_string())
+      .append_pair("filter[kind]", "anime")
+      .append_pair("filter[status]", "current")
+      .append_pair("fields[libraryEntries]", "progress,status,updatedAt,anime")
+      .append_pair("fields[anime]", "canonicalTitle,titles,episodeCount,status")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+    authorize(&mut req, token);
+
+    Box::new(self.request::<Vec<Entry>>(req).map(|doc| {
+      let (_, next) = doc.prev_next();
+      let (entries, animes) = doc.included_anime();
+      (next, entries, animes)
+    }))
+  }
+
+  /// Fetches every anime the user is currently watching, page by page.
+  pub fn fetch_watching(
+    &self,
+    token: Option<String>,
+    user_id: i64,
+  ) -> Box<Future<Item = Vec<(Entry, Anime)>, Error = Error>> {
+    let api = self.clone();
+    Box::new(loop_fn(
+      (0, Vec::new()),
+      move |(offset, mut shows): (usize, Vec<(Entry, Anime)>)| {
+        api
+          .fetch_watching_page(token.clone(), user_id, offset)
+          .and_then(move |(next, entries, animes)| {
+            let offset = offset + entries.len();
+            let done = next.is_none() || entries.is_empty();
+            shows.extend(pair_anime(entries, animes));
+            if done {
+              Ok(Loop::Break(shows))
+            } else {
+              Ok(Loop::Continue((offset, shows)))
+            }
+          })
+      },
+    ))
+  }
+
+  /// Returns the number of the latest episode that already aired.
+  pub fn latest_episode(&self, anime_id: &str) -> Box<Future<Item = Option<i64>, Error = Error>> {
     Box::new(
       self
-        .request(req)
-        .and_then(|res| match res {
-          Json::AnimeEntry { data, included, links, .. } => Ok((data, included, links)),
-          _ => Err(Error::Kitsu(KitsuError {
-            description: String::from("Invalid JSON"),
-          })),
+        .aired_episodes(anime_id)
+        .map(|episodes| episodes.into_iter().map(|(number, _)| number).max()),
+    )
+  }
+
+  /// Returns the number and airdate of the latest episodes that already
+  /// aired.
+  pub fn aired_episodes(
+    &self,
+    anime_id: &str,
+  ) -> Box<Future<Item = Vec<(i64, NaiveDate)>, Error = Error>> {
+    let mut endpoint = self
+      .base
+      .join(&format!("anime/{}/episodes", anime_id))
+      .unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("sort", "-number")
+      .append_pair("page[limit]", "20")
+      .append_pair("fields[episodes]", "number,airdate")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+
+    Box::new(self.request::<Vec<Episode>>(req).map(|doc| {
+      let today = Utc::today().naive_utc();
+      doc
+        .data
+        .into_iter()
+        .filter_map(|episode| {
+          let number = episode.attributes.number;
+          episode
+            .attributes
+            .airdate
+            .and_then(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok())
+            .and_then(|date| if date <= today { Some((number, date)) } else { None })
         })
-        .and_then(|(entries, animes, links)| {
-          Ok((links.prev, links.next, entries, animes))
-        }),
+        .collect()
+    }))
+  }
+
+  pub fn fetch_trending(&self) -> Box<Future<Item = Vec<Anime>, Error = Error>> {
+    let mut endpoint = self.base.join("trending/anime").unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("limit", "10")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+
+    Box::new(self.request::<Vec<Anime>>(req).map(|doc| doc.data))
+  }
+
+  pub fn fetch_season(
+    &self,
+    season: Season,
+    year: i32,
+    offset: i64,
+  ) -> Box<Future<Item = (Option<String>, Option<String>, Vec<Anime>), Error = Error>> {
+    let mut endpoint = self.base.join("anime").unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("sort", "-userCount")
+      .append_pair("page[limit]", "5")
+      .append_pair("page[offset]", &offset.to_string())
+      .append_pair("filter[season]", season.as_str())
+      .append_pair("filter[seasonYear]", &year.to_string())
+      .append_pair(
+        "fields[anime]",
+        "canonicalTitle,titles,episodeCount,subtype,averageRating",
+      )
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+
+    Box::new(self.request::<Vec<Anime>>(req).map(|doc| {
+      let (prev, next) = doc.prev_next();
+      (prev, next, doc.data)
+    }))
+  }
+
+  fn fetch_library_page(
+    &self,
+    token: Option<String>,
+    user_id: i64,
+    offset: usize,
+  ) -> Box<Future<Item = (Option<String>, Vec<Entry>, Vec<Anime>, Vec<Mapping>), Error = Error>> {
+    let mut endpoint = self.base.join("library-entries").unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("include", "anime,anime.mappings")
+      .append_pair("page[limit]", "500")
+      .append_pair("page[offset]", &offset.to_string())
+      .append_pair("filter[user_id]", &user_id.to_string())
+      .append_pair("filter[kind]", "anime")
+      .append_pair(
+        "fields[libraryEntries]",
+        "progress,status,updatedAt,startedAt,finishedAt,ratingTwenty,anime",
+      )
+      .append_pair(
+        "fields[anime]",
+        "canonicalTitle,titles,episodeCount,subtype,mappings",
+      )
+      .append_pair("fields[mappings]", "externalSite,externalId")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+    authorize(&mut req, token);
+
+    Box::new(self.request::<Vec<Entry>>(req).map(|doc| {
+      let (_, next) = doc.prev_next();
+      let mut animes = Vec::new();
+      let mut mappings = Vec::new();
+      for i in doc.included {
+        match i {
+          Included::Anime(anime) => animes.push(anime),
+          Included::Mappings(mapping) => mappings.push(mapping),
+        }
+      }
+      (next, doc.data, animes, mappings)
+    }))
+  }
+
+  /// Fetches every anime entry in the user's library, page by page.
+  pub fn fetch_library(
+    &self,
+    token: Option<String>,
+    user_id: i64,
+  ) -> Box<Future<Item = (Vec<Entry>, Vec<Anime>, Vec<Mapping>), Error = Error>> {
+    let api = self.clone();
+    Box::new(loop_fn(
+      (Vec::new(), Vec::new(), Vec::new()),
+      move |(mut entries, mut animes, mut mappings): (Vec<Entry>, Vec<Anime>, Vec<Mapping>)| {
+        api
+          .fetch_library_page(token.clone(), user_id, entries.len())
+          .and_then(move |(next, e, a, m)| {
+            let done = next.is_none() || e.is_empty();
+            entries.extend(e);
+            animes.extend(a);
+            mappings.extend(m);
+            if done {
+              Ok(Loop::Break((entries, animes, mappings)))
+            } else {
+              Ok(Loop::Continue((entries, animes, mappings)))
+            }
+          })
+      },
+    ))
+  }
+
+  pub fn get_user(&self, user_id: i64) -> Box<Future<Item = User, Error = Error>> {
+    let mut endpoint = self.base.join("users/").unwrap().join(&user_id.to_string()).unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("fields[users]", "name,titleLanguagePreference")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+
+    Box::new(self.request::<User>(req).map(|doc| doc.data))
+  }
+
+  pub fn get_title_preference(
+    &self,
+    user_id: i64,
+  ) -> Box<Future<Item = TitleLanguagePreference, Error = Error>> {
+    Box::new(
+      self
+        .get_user(user_id)
+        .map(|user| user.attributes.title_language_preference),
     )
   }
 
   pub fn get_anime(
     &self,
+    token: Option<String>,
     user_id: i64,
-    anime_id: i64,
+    anime_id: &str,
   ) -> Box<Future<Item = Option<(Entry, Anime)>, Error = Error>> {
     let mut endpoint = self.base.join("library-entries").unwrap();
 
@@ -106,7 +442,35 @@ impl Api {
       .query_pairs_mut()
       .append_pair("include", "anime")
       .append_pair("filter[user_id]", &user_id.to_string())
-      .append_pair("filter[anime_id]", &anime_id.to_string())
+      .append_pair("filter[anime_id]", anime_id)
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+    authorize(&mut req, token);
+
+    Box::new(self.request::<Vec<Entry>>(req).map(|doc| {
+      let (mut entries, mut animes) = doc.included_anime();
+      match (entries.pop(), animes.pop()) {
+        (Some(entry), Some(anime)) => Some((entry, anime)),
+        _ => None,
+      }
+    }))
+  }
+
+  pub fn get_genres(&self, anime_id: &str) -> Box<Future<Item = Vec<String>, Error = Error>> {
+    let mut endpoint = self
+      .base
+      .join(&format!("anime/{}/genres", anime_id))
+      .unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("fields[genres]", "name")
       .finish()
       .as_str();
 
@@ -118,26 +482,133 @@ impl Api {
 
     Box::new(
       self
-        .request(req)
-        .and_then(|res| match res {
-          Json::AnimeEntry { mut data, mut included, .. } => Ok((data.pop(), included.pop())),
-          _ => Err(Error::Kitsu(KitsuError {
-            description: String::from("Invalid JSON"),
-          })),
-        })
-        .and_then(|(entry, anime)| match (entry, anime) {
-          (Some(entry), Some(anime)) => Ok(Some((entry, anime))),
-          _ => Ok(None),
-        }),
+        .request::<Vec<Genre>>(req)
+        .map(|doc| doc.data.into_iter().map(|g| g.attributes.name).collect()),
     )
   }
 
-  pub fn update_anime_entry(
+  pub fn get_entry(
+    &self,
+    token: Option<String>,
+    entry_id: &str,
+  ) -> Box<Future<Item = (Entry, Option<Anime>), Error = Error>> {
+    let mut endpoint = self
+      .base
+      .join("library-entries/")
+      .unwrap()
+      .join(entry_id)
+      .unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("include", "anime")
+      .append_pair(
+        "fields[libraryEntries]",
+        "progress,status,updatedAt,reconsuming,reconsumeCount,anime",
+      )
+      .append_pair("fields[anime]", "canonicalTitle,titles,episodeCount")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+    authorize(&mut req, token);
+
+    Box::new(self.request::<Entry>(req).map(|doc| {
+      let (entry, mut animes) = doc.included_anime();
+      (entry, animes.pop())
+    }))
+  }
+
+  /// Resolves MyAnimeList anime ids to Kitsu anime ids.
+  pub fn find_mal_mappings(
     &self,
+    mal_ids: &[String],
+  ) -> Box<Future<Item = Vec<(String, String)>, Error = Error>> {
+    let mut endpoint = self.base.join("mappings").unwrap();
+
+    let url = endpoint
+      .query_pairs_mut()
+      .append_pair("include", "item")
+      .append_pair("page[limit]", &mal_ids.len().to_string())
+      .append_pair("filter[externalSite]", "myanimelist/anime")
+      .append_pair("filter[externalId]", &mal_ids.join(","))
+      .append_pair("fields[mappings]", "externalSite,externalId,item")
+      .append_pair("fields[anime]", "canonicalTitle,titles")
+      .finish()
+      .as_str();
+
+    let uri = Uri::from_str(url).unwrap();
+    let mut req = Request::new(Method::Get, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+
+    Box::new(self.request::<Vec<Mapping>>(req).map(|doc| {
+      doc
+        .data
+        .into_iter()
+        .filter_map(|mapping| {
+          let external_id = mapping.attributes.external_id;
+          mapping
+            .relationships
+            .and_then(|r| r.item)
+            .and_then(|item| item.data)
+            .map(|item| (external_id, item.id))
+        })
+        .collect()
+    }))
+  }
+
+  fn save_entry(
+    &self,
+    method: Method,
+    url: Url,
     token: String,
-    entry_id: String,
-    progress: i64,
+    entry: Entry,
+  ) -> Box<Future<Item = Entry, Error = Error>> {
+    let uri = Uri::from_str(url.as_str()).unwrap();
+    let body = to_string(&Document::new(entry)).expect("error/json-to-string");
+
+    let mut req = Request::new(method, uri);
+    req.headers_mut().set(ContentType(
+      Mime::from_str("application/vnd.api+json").unwrap(),
+    ));
+    req.headers_mut().set(Authorization(Bearer { token }));
+    req.headers_mut().set(ContentLength(body.len() as u64));
+    req.set_body(body);
+
+    Box::new(self.request::<Entry>(req).map(|doc| doc.data))
+  }
+
+  pub fn create_entry(
+    &self,
+    token: String,
+    user_id: i64,
     anime_id: String,
+    attributes: EntryAttributes,
+  ) -> Box<Future<Item = Entry, Error = Error>> {
+    let url = self.base.join("library-entries").unwrap();
+    let entry = Entry {
+      id: String::new(),
+      kind: Type::LibraryEntries,
+      attributes: Some(attributes),
+      relationships: Some(Relationships {
+        anime: Some(ToOne::new(Type::Anime, anime_id)),
+        user: Some(ToOne::new(Type::Users, user_id.to_string())),
+      }),
+    };
+    self.save_entry(Method::Post, url, token, entry)
+  }
+
+  pub fn update_entry(
+    &self,
+    token: String,
+    entry_id: String,
+    attributes: EntryAttributes,
   ) -> Box<Future<Item = Entry, Error = Error>> {
     let url = self
       .base
@@ -145,37 +616,49 @@ impl Api {
       .unwrap()
       .join(&entry_id)
       .unwrap();
-    let uri = Uri::from_str(url.as_str()).unwrap();
-
-    let json = Json::Entry {
-      data: Entry {
-        id: entry_id,
-        kind: Type::LibraryEntries,
-        attributes: Some(EntryAttributes {
-          status: None,
-          updated_at: None,
-          progress: Some(progress),
-        }),
-        relationships: Some(Relationships {
-          anime: Some(Anime { id: anime_id, attributes: None }),
-        }),
-      },
+    let entry = Entry {
+      id: entry_id,
+      kind: Type::LibraryEntries,
+      attributes: Some(attributes),
+      relationships: None,
     };
-    let body = to_string(&json).expect("error/json-to-string");
+    self.save_entry(Method::Patch, url, token, entry)
+  }
 
-    let mut req = Request::new(Method::Patch, uri);
-    req.headers_mut().set(ContentType(
-      Mime::from_str("application/vnd.api+json").unwrap(),
-    ));
+  /// Revokes an OAuth access token, so it can't be used anymore.
+  pub fn revoke_token(&self, token: String) -> Box<Future<Item = (), Error = Error>> {
+    let url = self.base.join("/api/oauth/revoke").unwrap();
+    let uri = Uri::from_str(url.as_str()).unwrap();
+    let body = form_urlencoded::Serializer::new(String::new())
+      .append_pair("token", &token)
+      .finish();
+
+    let mut req = Request::new(Method::Post, uri);
+    req.headers_mut().set(ContentType::form_url_encoded());
     req.headers_mut().set(Authorization(Bearer { token }));
     req.headers_mut().set(ContentLength(body.len() as u64));
     req.set_body(body);
 
-    Box::new(self.request(req).and_then(|res| match res {
-      Json::Entry { data } => Ok(data),
-      _ => Err(Error::Kitsu(KitsuError {
-        description: String::from("Invalid JSON"),
-      })),
-    }))
+    Box::new(
+      self
+        .client
+        .request(req)
+        .from_err::<Error>()
+        .and_then(|res| if res.status().is_success() {
+          Ok(())
+        } else {
+          Err(Error::Kitsu(KitsuError {
+            description: format!("Failed to revoke token: {}", res.status()),
+          }))
+        }),
+    )
+  }
+}
+
+/// Library reads are made as their owner when we can, since Kitsu hides
+/// private entries from anonymous requests.
+fn authorize(req: &mut Request, token: Option<String>) {
+  if let Some(token) = token {
+    req.headers_mut().set(Authorization(Bearer { token }));
   }
 }
@@ -1,14 +1,21 @@
-#![feature(custom_attribute)]
+// written for Rust 2015 and the chrono, nom and futures APIs of the time
+#![allow(bare_trait_objects, ellipsis_inclusive_range_patterns, deprecated)]
+#![allow(clippy::too_many_arguments, clippy::type_complexity, clippy::new_ret_no_self)]
+#![allow(clippy::redundant_static_lifetimes, clippy::needless_borrowed_reference)]
+#![allow(clippy::match_like_matches_macro, clippy::upper_case_acronyms)]
+#![allow(clippy::large_enum_variant, clippy::unnecessary_map_or)]
 
 extern crate chrono;
+extern crate chrono_tz;
 extern crate env_logger;
 extern crate futures;
 extern crate hyper;
-extern crate hyper_tls;
+extern crate hyper_openssl;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate nom;
+extern crate ring;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -16,15 +23,35 @@ extern crate serde_json;
 extern crate tokio_core;
 extern crate url;
 
+mod admin;
 mod bot;
+mod callback;
+mod config;
+mod crypto;
 mod error;
 mod kitsu;
 mod utils;
+mod persist;
 mod types;
 mod handler;
 mod database;
+mod export;
+mod import;
+mod undo;
+mod settings;
+mod notify;
+mod digest;
+mod reminder;
+mod scheduler;
+mod templates;
 
-use futures::Stream;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{done, Future, Stream};
+
+use config::Config;
+use scheduler::Schedule;
 use types::telegram::Update;
 
 fn main() {
@@ -32,16 +59,135 @@ fn main() {
 
   env_logger::init().expect("error/init-logger");
 
+  let config = Config::from_env();
+
   let mut core = tokio_core::reactor::Core::new().expect("error/init-core");
   let handle = core.handle();
 
   let client = hyper::Client::configure()
-    .connector(hyper_tls::HttpsConnector::new(4, &handle).expect("error/create-connector"))
+    .connector(hyper_openssl::HttpsConnector::new(4, &handle).expect("error/create-connector"))
     .build(&handle);
 
-  let tg_bot = bot::telegram::Bot::new(TOKEN, client.clone());
+  let callbacks = callback::CallbackRegistry::load(config.callbacks_file);
+  callbacks.start(&handle);
+
+  let tg_bot = bot::telegram::Bot::new(TOKEN, client.clone(), callbacks.clone());
+
+  // self-hosters can keep users in a local file instead of firebase
+  let store_is_local = config.users_file.is_some();
+  let store: Rc<database::UserStore> = match config.users_file {
+    Some(path) => Rc::new(database::FileStore::new(path)),
+    None => Rc::new(database::FirebaseStore::new(TOKEN.to_string(), client.clone())),
+  };
+  let cipher = match config.token_key {
+    Some(key) => crypto::Cipher::new(key, config.old_token_keys),
+    None if store_is_local => panic!("TOKEN_KEY is required to keep users in USERS_FILE"),
+    None => {
+      // logged as an error, since warnings are hidden unless RUST_LOG asks for them
+      error!(
+        "TOKEN_KEY isn't set, tokens are encrypted with a random key that's lost on exit \
+         and /rotatekey does nothing useful; set TOKEN_KEY to keep a key"
+      );
+      crypto::Cipher::ephemeral()
+    }
+  };
+  let db = database::Database::new(store, Rc::new(cipher), config.active_file);
+
+  let admin = admin::Admin::load(config.admins, config.bans_file);
+
+  let settings = settings::SettingsStore::load(config.settings_file, config.shared_file);
+  let notify = notify::NotifyStore::load(config.airing_file);
+  let templates = templates::Templates::load(config.templates_file);
+
+  let scheduler = scheduler::Scheduler::load(config.jobs_file, config.max_jobs);
+
+  // load the users at startup, then keep them fresh in the background.
+  // a failed refresh leaves the previous users in place.
+  handle.spawn(db.clone().fetch().then(|res| {
+    match res {
+      Ok(users) => info!("loaded users: {} user(s)", users.len()),
+      Err(e) => error!("failed to load users: {:?}", e),
+    }
+    Ok(())
+  }));
+  if let Some(interval) = config.refresh_interval {
+    let refreshing = db.clone();
+    scheduler.register("refresh-users", Schedule::Every(interval), move || {
+      refreshing
+        .clone()
+        .fetch()
+        .map(|users| format!("refreshed users: {} user(s)", users.len()))
+    });
+  }
+
+  let expiring = callbacks.clone();
+  scheduler.register(
+    "expire-callbacks",
+    Schedule::Cron {
+      minute: 0,
+      hour: None,
+      weekday: None,
+    },
+    move || done(Ok(format!("expired {} callback(s)", expiring.expire()))),
+  );
+
+  if let Some(interval) = config.notify_interval {
+    let notifier = notify::EpisodeNotifier::new(
+      tg_bot.clone(),
+      client.clone(),
+      db.clone(),
+      admin.clone(),
+      settings.clone(),
+      notify.clone(),
+    );
+    scheduler.register("notify-episodes", Schedule::Every(interval), move || {
+      notifier
+        .run()
+        .map(|sent| format!("sent {} episode notification(s)", sent))
+    });
+  }
+
+  let digests = digest::DigestSender::new(
+    tg_bot.clone(),
+    client.clone(),
+    db.clone(),
+    admin.clone(),
+    settings.clone(),
+    notify.clone(),
+  );
+  scheduler.register(
+    "send-digests",
+    Schedule::Every(Duration::from_secs(digest::CHECK_INTERVAL)),
+    move || digests.run().map(|sent| format!("sent {} digest(s)", sent)),
+  );
+
+  let reminders = reminder::ReminderSender::new(
+    tg_bot.clone(),
+    client.clone(),
+    db.clone(),
+    admin.clone(),
+    settings.clone(),
+    notify.clone(),
+  );
+  scheduler.register(
+    "send-reminders",
+    Schedule::Every(Duration::from_secs(reminder::CHECK_INTERVAL)),
+    move || reminders.run().map(|sent| format!("sent {} reminder(s)", sent)),
+  );
+
+  scheduler.start(&handle);
 
-  let mut handler = handler::Handler::new(tg_bot.clone(), client.clone(), TOKEN.to_string());
+  let mut handler = handler::Handler::new(
+    tg_bot.clone(),
+    client.clone(),
+    db,
+    callbacks,
+    admin,
+    settings,
+    notify,
+    scheduler,
+    templates,
+  );
 
   let work = bot::telegram::UpdateStream::new(tg_bot)
     .filter_map(|up| match up {
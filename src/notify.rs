@@ -0,0 +1,357 @@
+use std::rc::Rc;
+use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+
+use futures::{done, stream, Future, Stream};
+
+use persist;
+use admin::Admin;
+use bot::telegram::Bot;
+use kitsu::Api;
+use error::Error;
+use settings::SettingsStore;
+use database::Database;
+use types::{bind_keyboard, Client};
+use types::kitsu::{Anime, AnimeStatus, Entry, TitleLanguagePreference};
+use utils::parse_new_episode;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Airing {
+  // latest episode each user was told about, keyed by telegram id and
+  // then by anime id
+  notified: HashMap<i64, HashMap<String, i64>>,
+  muted: HashMap<i64, HashSet<String>>,
+  // when each user got their latest digest
+  #[serde(default)] digests: HashMap<i64, DateTime<Utc>>,
+  // when each user was last reminded of a stale entry, keyed by entry id
+  #[serde(default)] reminded: HashMap<i64, HashMap<String, DateTime<Utc>>>,
+}
+
+/// Remembers which episodes were announced, which shows users don't want
+/// to hear about, and when digests and reminders were sent.
+#[derive(Clone)]
+pub struct NotifyStore {
+  path: PathBuf,
+  airing: Rc<RefCell<Airing>>,
+}
+
+impl NotifyStore {
+  /// Loads the state saved at `path`, if any.
+  pub fn load(path: PathBuf) -> NotifyStore {
+    let airing = persist::load(&path, "notifications");
+    NotifyStore {
+      path,
+      airing: Rc::new(RefCell::new(airing)),
+    }
+  }
+
+  fn save(&self) {
+    persist::save(&self.path, &*self.airing.borrow(), "notifications");
+  }
+
+  pub fn is_muted(&self, telegram_id: i64, anime_id: &str) -> bool {
+    self
+      .airing
+      .borrow()
+      .muted
+      .get(&telegram_id)
+      .map_or(false, |muted| muted.contains(anime_id))
+  }
+
+  pub fn set_muted(&self, telegram_id: i64, anime_id: &str, muted: bool) {
+    {
+      let mut airing = self.airing.borrow_mut();
+      let shows = airing.muted.entry(telegram_id).or_default();
+      if muted {
+        shows.insert(anime_id.to_owned());
+      } else {
+        shows.remove(anime_id);
+      }
+    }
+    self.save();
+  }
+
+  /// The latest aired episode of the show the user knows about, or `None`
+  /// if it wasn't checked before.
+  fn last_notified(&self, telegram_id: i64, anime_id: &str) -> Option<i64> {
+    self
+      .airing
+      .borrow()
+      .notified
+      .get(&telegram_id)
+      .and_then(|shows| shows.get(anime_id).cloned())
+  }
+
+  /// Records that the user knows about `episode` of the show.
+  /// should be told about it; a show seen for the first time is only
+  /// recorded, so turning notifications on doesn't announce old episodes.
+  fn record(&self, telegram_id: i64, anime_id: &str, episode: i64) {
+    let mut airing = self.airing.borrow_mut();
+    let last = airing
+      .notified
+      .entry(telegram_id)
+      .or_default()
+      .entry(anime_id.to_owned())
+      .or_insert(episode);
+    if episode > *last {
+      *last = episode;
+    }
+  }
+
+  pub fn last_digest(&self, telegram_id: i64) -> Option<DateTime<Utc>> {
+    self.airing.borrow().digests.get(&telegram_id).cloned()
+  }
+
+  pub fn set_last_digest(&self, telegram_id: i64, time: DateTime<Utc>) {
+    self.airing.borrow_mut().digests.insert(telegram_id, time);
+    self.save();
+  }
+
+  /// Whether the user should be reminded of an entry they last updated at
+  /// `updated_at`. An ignored reminder is only repeated after another
+  /// `period`.
+  pub fn remind_due(
+    &self,
+    telegram_id: i64,
+    entry_id: &str,
+    updated_at: DateTime<Utc>,
+    period: Duration,
+  ) -> bool {
+    let since = self
+      .airing
+      .borrow()
+      .reminded
+      .get(&telegram_id)
+      .and_then(|reminded| reminded.get(entry_id))
+      .map_or(updated_at, |&at| at.max(updated_at));
+    Utc::now().signed_duration_since(since) > period
+  }
+
+  /// Records that the user was just reminded of an entry.
+  pub fn set_reminded(&self, telegram_id: i64, entry_id: &str) {
+    self
+      .airing
+      .borrow_mut()
+      .reminded
+      .entry(telegram_id)
+      .or_default()
+      .insert(entry_id.to_owned(), Utc::now());
+    self.save();
+  }
+
+  pub fn forget(&self, telegram_id: i64) {
+    let removed = {
+      let mut airing = self.airing.borrow_mut();
+      let notified = airing.notified.remove(&telegram_id).is_some();
+      let muted = airing.muted.remove(&telegram_id).is_some();
+      let reminded = airing.reminded.remove(&telegram_id).is_some();
+      airing.digests.remove(&telegram_id).is_some() || notified || muted || reminded
+    };
+    if removed {
+      self.save();
+    }
+  }
+}
+
+/// Tells users about new episodes of the shows they're watching.
+#[derive(Clone)]
+pub struct EpisodeNotifier {
+  api: Api,
+  bot: Bot,
+  db: Database,
+  admin: Admin,
+  settings: SettingsStore,
+  store: NotifyStore,
+}
+
+impl EpisodeNotifier {
+  pub fn new(
+    bot: Bot,
+    client: Client,
+    db: Database,
+    admin: Admin,
+    settings: SettingsStore,
+    store: NotifyStore,
+  ) -> EpisodeNotifier {
+    EpisodeNotifier {
+      bot,
+      db,
+      admin,
+      settings,
+      store,
+      api: Api::new(client),
+    }
+  }
+
+  /// Checks the shows of everyone who turned notifications on. Returns how
+  /// many notifications were sent.
+  pub fn run(&self) -> Box<Future<Item = usize, Error = Error>> {
+    if self.admin.in_maintenance() {
+      return Box::new(done(Ok(0)));
+    }
+    let mut db = self.db.clone();
+    let mut subscribers: Vec<(i64, i64, Option<String>, _)> = Vec::new();
+    for user in self.db.users() {
+      let telegram_id = user.telegram_id;
+      let settings = self.settings.get(telegram_id);
+      if subscribers.iter().any(|&(id, _, _, _)| id == telegram_id) ||
+        self.admin.is_banned(telegram_id) || !settings.notify_episodes
+      {
+        continue;
+      }
+      if let Some(kitsu_id) = db.get_kitsu_id(telegram_id) {
+        let token = db.get_token(telegram_id, kitsu_id);
+        subscribers.push((telegram_id, kitsu_id, token, settings.title_language));
+      }
+    }
+    let api = self.api.clone();
+    let notifier = self.clone();
+    Box::new(
+      stream::iter_ok(subscribers)
+        .and_then(move |(telegram_id, kitsu_id, token, title_language)| {
+          // the language picked in the settings, or else the one of the Kitsu account
+          let preference = match title_language {
+            Some(preference) => Box::new(done(Ok(preference))),
+            None => api.get_title_preference(kitsu_id),
+          };
+          let preference = preference.or_else(move |e| {
+            error!("failed to fetch the title preference of {}: {:?}", telegram_id, e);
+            Ok::<_, Error>(TitleLanguagePreference::default())
+          });
+          api
+            .fetch_watching(token, kitsu_id)
+            .then(move |res| match res {
+              Ok(shows) => Ok(shows),
+              Err(e) => {
+                error!("failed to fetch the shows of {}: {:?}", telegram_id, e);
+                Ok(Vec::new())
+              }
+            })
+            .join(preference)
+            .map(move |(shows, preference)| (telegram_id, kitsu_id, preference, shows))
+        })
+        .collect()
+        .and_then(move |watching| notifier.check(watching)),
+    )
+  }
+
+  fn check(
+    &self,
+    watching: Vec<(i64, i64, TitleLanguagePreference, Vec<(Entry, Anime)>)>,
+  ) -> Box<Future<Item = usize, Error = Error>> {
+    // only shows still airing can get new episodes
+    let watching: Vec<_> = watching
+      .into_iter()
+      .map(|(telegram_id, kitsu_id, preference, shows)| {
+        let shows: Vec<_> = shows
+          .into_iter()
+          .filter(|&(_, ref anime)| {
+            match anime.attributes.as_ref().and_then(|attr| attr.status.as_ref()) {
+              Some(&AnimeStatus::Current) => true,
+              _ => false,
+            }
+          })
+          .collect();
+        (telegram_id, kitsu_id, preference, shows)
+      })
+      .collect();
+    let mut anime_ids: Vec<String> = watching
+      .iter()
+      .flat_map(|&(_, _, _, ref shows)| shows.iter().map(|&(_, ref anime)| anime.id.clone()))
+      .collect();
+    anime_ids.sort();
+    anime_ids.dedup();
+
+    let api = self.api.clone();
+    let notifier = self.clone();
+    Box::new(
+      stream::iter_ok(anime_ids)
+        .and_then(move |anime_id| {
+          api.latest_episode(&anime_id).then(move |res| match res {
+            Ok(episode) => Ok((anime_id, episode)),
+            Err(e) => {
+              error!("failed to fetch the episodes of {}: {:?}", anime_id, e);
+              Ok((anime_id, None))
+            }
+          })
+        })
+        .collect()
+        .and_then(move |aired| {
+          let aired = aired
+            .into_iter()
+            .filter_map(|(anime_id, episode)| episode.map(|episode| (anime_id, episode)))
+            .collect();
+          notifier.notify(watching, aired)
+        }),
+    )
+  }
+
+  fn notify(
+    &self,
+    watching: Vec<(i64, i64, TitleLanguagePreference, Vec<(Entry, Anime)>)>,
+    aired: HashMap<String, i64>,
+  ) -> Box<Future<Item = usize, Error = Error>> {
+    let mut messages = Vec::new();
+    for (telegram_id, kitsu_id, preference, shows) in watching {
+      for (entry, anime) in shows {
+        let episode = match aired.get(&anime.id) {
+          Some(&episode) => episode,
+          None => continue,
+        };
+        let progress = entry
+          .attributes
+          .as_ref()
+          .and_then(|attr| attr.progress)
+          .unwrap_or(0);
+        match self.store.last_notified(telegram_id, &anime.id) {
+          Some(last) if episode <= last => continue,
+          // the first check only notes how far the show is
+          None => {
+            self.store.record(telegram_id, &anime.id, episode);
+            continue;
+          }
+          Some(_) => (),
+        }
+        if progress >= episode || self.store.is_muted(telegram_id, &anime.id) {
+          self.store.record(telegram_id, &anime.id, episode);
+          continue;
+        }
+        let title = match anime.attributes {
+          Some(ref attr) => attr.title(&preference).to_owned(),
+          None => String::from("an anime you're watching"),
+        };
+        let (text, buttons) = parse_new_episode(kitsu_id, &anime.id, &entry.id, &title, episode);
+        messages.push((telegram_id, anime.id.clone(), episode, text, buttons));
+      }
+    }
+    self.store.save();
+
+    let bot = self.bot.clone();
+    let store = self.store.clone();
+    Box::new(
+      stream::iter_ok(messages)
+        .and_then(move |(chat_id, anime_id, episode, text, buttons)| {
+          let store = store.clone();
+          bot
+            .send_message(chat_id, text, None, Some(bind_keyboard(buttons, chat_id)))
+            .then(move |res| match res {
+              Ok(msg) => {
+                info!("send message: {:?} in {:?}", msg.text, msg.chat);
+                // only recorded once sent, so a failed notification is tried again
+                store.record(chat_id, &anime_id, episode);
+                store.save();
+                Ok(true)
+              }
+              Err(e) => {
+                error!("failed to notify {}: {:?}", chat_id, e);
+                Ok(false)
+              }
+            })
+        })
+        .fold(0, |sent, ok| Ok::<usize, Error>(if ok { sent + 1 } else { sent })),
+    )
+  }
+}
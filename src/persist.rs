@@ -0,0 +1,49 @@
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{from_reader, to_writer};
+
+use error::Error;
+
+/// Loads the state saved at `path`. A missing file means there's nothing
+/// saved yet. A file that can't be read is logged and moved aside, so the
+/// next save doesn't overwrite what's left of it.
+pub fn load<T: DeserializeOwned + Default>(path: &Path, what: &str) -> T {
+  let file = match File::open(path) {
+    Ok(file) => file,
+    Err(ref e) if e.kind() == ErrorKind::NotFound => return T::default(),
+    Err(e) => {
+      error!("failed to load {} from {:?}: {:?}", what, path, e);
+      return T::default();
+    }
+  };
+  match from_reader(file) {
+    Ok(state) => state,
+    Err(e) => {
+      let aside = path.with_extension("corrupt");
+      error!("failed to load {} from {:?}, moved to {:?}: {:?}", what, path, aside, e);
+      if let Err(e) = fs::rename(path, &aside) {
+        error!("failed to move {:?} aside: {:?}", path, e);
+      }
+      T::default()
+    }
+  }
+}
+
+/// Saves the state to `path`, logging failures.
+pub fn save<T: Serialize>(path: &Path, state: &T, what: &str) {
+  if let Err(e) = write(path, state) {
+    error!("failed to save {}: {:?}", what, e);
+  }
+}
+
+fn write<T: Serialize>(path: &Path, state: &T) -> Result<(), Error> {
+  // write next to the file first, so a crash can't leave it half written
+  let tmp = path.with_extension("tmp");
+  to_writer(File::create(&tmp)?, state)?;
+  fs::rename(&tmp, path)?;
+  Ok(())
+}
@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+
+use chrono::{Duration, Utc};
+
+use futures::{done, stream, Future, Stream};
+
+use admin::Admin;
+use bot::telegram::Bot;
+use kitsu::Api;
+use error::Error;
+use notify::NotifyStore;
+use settings::SettingsStore;
+use database::Database;
+use types::{bind_keyboard, Client, Keyboard};
+use types::kitsu::{Anime, Entry};
+use types::telegram::ParseMode;
+use utils::parse_reminder;
+
+/// Seconds between two looks for stale entries.
+pub const CHECK_INTERVAL: u64 = 3600;
+
+/// At most this many reminders are sent to a user at once, so turning
+/// reminders on for a neglected library doesn't flood the chat.
+const MAX_REMINDERS: usize = 3;
+
+/// Reminds users of `current` entries they haven't updated for a while.
+#[derive(Clone)]
+pub struct ReminderSender {
+  api: Api,
+  bot: Bot,
+  db: Database,
+  admin: Admin,
+  settings: SettingsStore,
+  store: NotifyStore,
+}
+
+impl ReminderSender {
+  pub fn new(
+    bot: Bot,
+    client: Client,
+    db: Database,
+    admin: Admin,
+    settings: SettingsStore,
+    store: NotifyStore,
+  ) -> ReminderSender {
+    ReminderSender {
+      bot,
+      db,
+      admin,
+      settings,
+      store,
+      api: Api::new(client),
+    }
+  }
+
+  /// Sends the reminders that are due. Returns how many were sent.
+  pub fn run(&self) -> Box<Future<Item = usize, Error = Error>> {
+    if self.admin.in_maintenance() {
+      return Box::new(done(Ok(0)));
+    }
+    let mut db = self.db.clone();
+    let mut seen = HashSet::new();
+    let mut subscribers = Vec::new();
+    for user in self.db.users() {
+      let telegram_id = user.telegram_id;
+      if !seen.insert(telegram_id) || self.admin.is_banned(telegram_id) ||
+        !self.settings.get(telegram_id).notify_reminders
+      {
+        continue;
+      }
+      if let Some(kitsu_id) = db.get_kitsu_id(telegram_id) {
+        subscribers.push((telegram_id, kitsu_id, db.get_token(telegram_id, kitsu_id)));
+      }
+    }
+
+    let api = self.api.clone();
+    let sender = self.clone();
+    Box::new(
+      stream::iter_ok::<_, Error>(subscribers)
+        .and_then(move |(telegram_id, kitsu_id, token)| {
+          api.fetch_watching(token, kitsu_id).then(move |res| match res {
+            Ok(shows) => Ok((telegram_id, kitsu_id, shows)),
+            Err(e) => {
+              error!("failed to fetch the shows of {}: {:?}", telegram_id, e);
+              Ok((telegram_id, kitsu_id, Vec::new()))
+            }
+          })
+        })
+        .map(move |(telegram_id, kitsu_id, shows)| {
+          stream::iter_ok::<_, Error>(sender.reminders(telegram_id, kitsu_id, shows))
+        })
+        .flatten()
+        .and_then({
+          let bot = self.bot.clone();
+          let store = self.store.clone();
+          move |(chat_id, entry_id, text, buttons)| {
+            let store = store.clone();
+            bot
+              .send_message(
+                chat_id,
+                text,
+                Some(ParseMode::HTML),
+                Some(bind_keyboard(buttons, chat_id)),
+              )
+              .then(move |res| match res {
+                Ok(msg) => {
+                  info!("send message: {:?} in {:?}", msg.text, msg.chat);
+                  // only recorded once sent, so a failed reminder is tried again
+                  store.set_reminded(chat_id, &entry_id);
+                  Ok(true)
+                }
+                Err(e) => {
+                  error!("failed to remind {}: {:?}", chat_id, e);
+                  Ok(false)
+                }
+              })
+          }
+        })
+        .fold(0, |sent, ok| Ok::<usize, Error>(if ok { sent + 1 } else { sent })),
+    )
+  }
+
+  /// Picks the stale entries of a user, oldest first.
+  fn reminders(
+    &self,
+    telegram_id: i64,
+    kitsu_id: i64,
+    shows: Vec<(Entry, Anime)>,
+  ) -> Vec<(i64, String, String, Keyboard)> {
+    let now = Utc::now();
+    let settings = self.settings.get(telegram_id);
+    let period = Duration::days(settings.reminder_days as i64);
+    let preference = settings.title_language.clone().unwrap_or_default();
+    let mut stale: Vec<_> = shows
+      .into_iter()
+      .filter_map(|(entry, anime)| {
+        let updated_at = entry.attributes.as_ref().and_then(|attr| attr.updated_at);
+        updated_at.map(|updated_at| (updated_at, entry, anime))
+      })
+      .filter(|&(updated_at, _, _)| now.signed_duration_since(updated_at) > period)
+      .collect();
+    stale.sort_by_key(|&(updated_at, _, _)| updated_at);
+
+    let mut reminders = Vec::new();
+    for (updated_at, entry, anime) in stale {
+      if reminders.len() == MAX_REMINDERS {
+        break;
+      }
+      if !self.store.remind_due(telegram_id, &entry.id, updated_at, period) {
+        continue;
+      }
+      let progress = entry
+        .attributes
+        .as_ref()
+        .and_then(|attr| attr.progress)
+        .unwrap_or(0);
+      let (title, episode_count) = match anime.attributes {
+        Some(ref attr) => (attr.title(&preference).to_owned(), attr.episode_count),
+        None => (String::from("an anime you're watching"), None),
+      };
+      let (text, buttons) = parse_reminder(
+        kitsu_id,
+        &anime.id,
+        &entry.id,
+        &title,
+        now.signed_duration_since(updated_at),
+        progress,
+        episode_count,
+      );
+      reminders.push((telegram_id, entry.id.clone(), text, buttons));
+    }
+    reminders
+  }
+}
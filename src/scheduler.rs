@@ -0,0 +1,327 @@
+use std::io;
+use std::rc::Rc;
+use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc, Weekday};
+
+use futures::{done, Future, Stream};
+
+use tokio_core::reactor::{Handle, Interval, Timeout};
+
+use persist;
+use error::Error;
+
+/// Seconds between two looks for jobs that are due.
+const TICK: u64 = 5;
+
+/// Seconds a run can take before it's given up, so a hung job frees its slot.
+const JOB_TIMEOUT: u64 = 600;
+
+/// Work done by a job. The returned text is kept as its last status.
+pub type Task = Rc<Fn() -> Box<Future<Item = String, Error = Error>>>;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Schedule {
+  /// Runs again this long after the previous run started.
+  Every(StdDuration),
+  /// Runs at the matching UTC time. `None` matches any hour or weekday.
+  Cron {
+    minute: u32,
+    hour: Option<u32>,
+    weekday: Option<Weekday>,
+  },
+}
+
+impl Schedule {
+  fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    match *self {
+      Schedule::Every(interval) => {
+        Some(time + Duration::from_std(interval).unwrap_or_else(|_| Duration::days(1)))
+      }
+      Schedule::Cron {
+        minute,
+        hour,
+        weekday,
+      } => {
+        // the next match is at most a week away, so minute steps are cheap enough
+        let start = time.with_second(0).and_then(|t| t.with_nanosecond(0));
+        let mut next = start.unwrap_or(time) + Duration::minutes(1);
+        for _ in 0..(7 * 24 * 60) {
+          if next.minute() == minute && hour.map_or(true, |hour| next.hour() == hour) &&
+            weekday.map_or(true, |weekday| next.weekday() == weekday)
+          {
+            return Some(next);
+          }
+          next += Duration::minutes(1);
+        }
+        None
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct JobStatus {
+  pub next_run: Option<DateTime<Utc>>,
+  pub last_run: Option<DateTime<Utc>>,
+  pub last_result: Option<Result<String, String>>,
+  /// The schedule `next_run` was worked out from.
+  #[serde(default)]
+  pub schedule: Option<String>,
+}
+
+struct Job {
+  name: String,
+  schedule: Schedule,
+  task: Task,
+  running: bool,
+}
+
+/// Runs timed jobs on the reactor. Next-run times are saved, so a restart
+/// neither repeats nor skips a run; a run missed while the bot was down
+/// happens once, as soon as it's back. A job whose schedule changed since
+/// is planned again from its new schedule.
+#[derive(Clone)]
+pub struct Scheduler {
+  path: PathBuf,
+  max_running: usize,
+  jobs: Rc<RefCell<Vec<Job>>>,
+  status: Rc<RefCell<HashMap<String, JobStatus>>>,
+}
+
+impl Scheduler {
+  /// Loads the job status saved at `path`, if any. At most `max_running`
+  /// jobs run at once; the others wait for a free slot.
+  pub fn load(path: PathBuf, max_running: usize) -> Scheduler {
+    let status = persist::load(&path, "jobs");
+    Scheduler {
+      path,
+      max_running: max_running.max(1),
+      jobs: Rc::new(RefCell::new(Vec::new())),
+      status: Rc::new(RefCell::new(status)),
+    }
+  }
+
+  fn save(&self) {
+    persist::save(&self.path, &*self.status.borrow(), "jobs");
+  }
+
+  pub fn register<F, T>(&self, name: &str, schedule: Schedule, task: F)
+  where
+    F: Fn() -> T + 'static,
+    T: Future<Item = String, Error = Error> + 'static,
+  {
+    let now = Utc::now();
+    {
+      let mut status = self.status.borrow_mut();
+      let status = status.entry(name.to_owned()).or_default();
+      let described = format!("{:?}", schedule);
+      if status.next_run.is_none() || status.schedule.as_ref() != Some(&described) {
+        status.next_run = schedule.next_after(now);
+        status.schedule = Some(described);
+      }
+      if status.next_run.is_none() {
+        return;
+      }
+    }
+    self.jobs.borrow_mut().push(Job {
+      schedule,
+      name: name.to_owned(),
+      task: Rc::new(move || Box::new(task()) as Box<Future<Item = String, Error = Error>>),
+      running: false,
+    });
+    self.save();
+  }
+
+  /// Starts looking for due jobs.
+  pub fn start(&self, handle: &Handle) {
+    let scheduler = self.clone();
+    let spawner = handle.clone();
+    handle.spawn(
+      Interval::new(StdDuration::from_secs(TICK), handle)
+        .expect("error/init-interval")
+        .map_err(|e| error!("scheduler timer: {:?}", e))
+        .for_each(move |_| {
+          scheduler.tick(&spawner);
+          Ok(())
+        }),
+    );
+  }
+
+  fn tick(&self, handle: &Handle) {
+    let now = Utc::now();
+    let mut due = Vec::new();
+    {
+      let mut jobs = self.jobs.borrow_mut();
+      let mut status = self.status.borrow_mut();
+      let mut free = self
+        .max_running
+        .saturating_sub(jobs.iter().filter(|job| job.running).count());
+      for job in jobs.iter_mut().filter(|job| !job.running) {
+        if free == 0 {
+          break;
+        }
+        let status = status.entry(job.name.clone()).or_default();
+        match status.next_run {
+          Some(next_run) if next_run <= now => (),
+          _ => continue,
+        }
+        job.running = true;
+        free -= 1;
+        status.last_run = Some(now);
+        status.next_run = job.schedule.next_after(now);
+        due.push((job.name.clone(), job.task.clone()));
+      }
+    }
+    if due.is_empty() {
+      return;
+    }
+    self.save();
+    for (name, task) in due {
+      let scheduler = self.clone();
+      let timeout = done(Timeout::new(StdDuration::from_secs(JOB_TIMEOUT), handle))
+        .flatten()
+        .then(|res| -> Result<String, Error> {
+          match res {
+            Ok(()) => Err(io::Error::new(io::ErrorKind::TimedOut, "job timed out").into()),
+            Err(e) => Err(e.into()),
+          }
+        });
+      handle.spawn(
+        task()
+          .select(timeout)
+          .map(|(text, _)| text)
+          .map_err(|(e, _)| e)
+          .then(move |res| {
+            scheduler.finish(&name, res);
+            Ok(())
+          }),
+      );
+    }
+  }
+
+  fn finish(&self, name: &str, res: Result<String, Error>) {
+    let res = match res {
+      Ok(text) => {
+        info!("job {}: {}", name, text);
+        Ok(text)
+      }
+      Err(e) => {
+        error!("job {} failed: {:?}", name, e);
+        Err(format!("{:?}", e))
+      }
+    };
+    if let Some(status) = self.status.borrow_mut().get_mut(name) {
+      status.last_result = Some(res);
+    }
+    for job in self.jobs.borrow_mut().iter_mut().filter(|job| job.name == name) {
+      job.running = false;
+    }
+    self.save();
+  }
+
+  /// Status of every registered job, with whether it's running right now.
+  pub fn jobs(&self) -> Vec<(String, bool, JobStatus)> {
+    let status = self.status.borrow();
+    self
+      .jobs
+      .borrow()
+      .iter()
+      .map(|job| {
+        (
+          job.name.clone(),
+          job.running,
+          status.get(&job.name).cloned().unwrap_or_default(),
+        )
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration as StdDuration;
+
+  use chrono::{DateTime, TimeZone, Utc, Weekday};
+
+  use super::Schedule;
+
+  fn at(y: i32, m: u32, d: u32, h: u32, min: u32, sec: u32) -> DateTime<Utc> {
+    Utc.ymd(y, m, d).and_hms(h, min, sec)
+  }
+
+  #[test]
+  fn every_adds_the_interval() {
+    let schedule = Schedule::Every(StdDuration::from_secs(1800));
+    assert_eq!(
+      schedule.next_after(at(2018, 1, 1, 23, 45, 10)),
+      Some(at(2018, 1, 2, 0, 15, 10))
+    );
+  }
+
+  #[test]
+  fn cron_every_hour() {
+    let schedule = Schedule::Cron {
+      minute: 0,
+      hour: None,
+      weekday: None,
+    };
+    assert_eq!(
+      schedule.next_after(at(2018, 1, 1, 10, 30, 15)),
+      Some(at(2018, 1, 1, 11, 0, 0))
+    );
+    // a run right at the matching minute is planned for the next hour
+    assert_eq!(
+      schedule.next_after(at(2018, 1, 1, 11, 0, 0)),
+      Some(at(2018, 1, 1, 12, 0, 0))
+    );
+  }
+
+  #[test]
+  fn cron_daily_rolls_over_to_the_next_day() {
+    let schedule = Schedule::Cron {
+      minute: 30,
+      hour: Some(8),
+      weekday: None,
+    };
+    assert_eq!(
+      schedule.next_after(at(2018, 1, 1, 7, 0, 0)),
+      Some(at(2018, 1, 1, 8, 30, 0))
+    );
+    assert_eq!(
+      schedule.next_after(at(2018, 1, 1, 9, 0, 0)),
+      Some(at(2018, 1, 2, 8, 30, 0))
+    );
+  }
+
+  #[test]
+  fn cron_weekly() {
+    let schedule = Schedule::Cron {
+      minute: 0,
+      hour: Some(9),
+      weekday: Some(Weekday::Mon),
+    };
+    // 2018-01-01 is a Monday
+    assert_eq!(
+      schedule.next_after(at(2018, 1, 1, 9, 0, 0)),
+      Some(at(2018, 1, 8, 9, 0, 0))
+    );
+    assert_eq!(
+      schedule.next_after(at(2018, 1, 3, 12, 0, 0)),
+      Some(at(2018, 1, 8, 9, 0, 0))
+    );
+  }
+
+  #[test]
+  fn cron_that_never_matches() {
+    let schedule = Schedule::Cron {
+      minute: 60,
+      hour: None,
+      weekday: None,
+    };
+    assert_eq!(schedule.next_after(at(2018, 1, 1, 0, 0, 0)), None);
+  }
+}
@@ -0,0 +1,377 @@
+use std::rc::Rc;
+use std::str::FromStr;
+use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use chrono::{Datelike, DateTime, Duration, TimeZone, Timelike, Utc, Weekday};
+
+use chrono_tz::Tz;
+
+use persist;
+use types::kitsu::TitleLanguagePreference;
+
+/// Page sizes offered by the settings menu.
+pub const PAGE_SIZES: [u32; 4] = [4, 6, 8, 10];
+
+/// How long an entry may go without updates before a reminder, in days.
+pub const REMINDER_PERIODS: [u32; 4] = [7, 14, 30, 60];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum RatingScale {
+  #[default]
+  Percent,
+  Ten,
+  Five,
+}
+
+impl RatingScale {
+  fn format(&self, percent: f64) -> String {
+    match *self {
+      RatingScale::Percent => format!("{:.0}%", percent),
+      RatingScale::Ten => format!("{:.1}/10", percent / 10.0),
+      RatingScale::Five => format!("{:.1}/5", percent / 20.0),
+    }
+  }
+
+  /// Formats an average rating, which Kitsu gives in percent. Percents
+  /// are shown as Kitsu gives them.
+  pub fn format_average(&self, percent: &str) -> String {
+    match (*self, f64::from_str(percent)) {
+      (RatingScale::Percent, _) | (_, Err(_)) => format!("{}%", percent),
+      (_, Ok(percent)) => self.format(percent),
+    }
+  }
+
+  /// Formats a user's rating, which Kitsu gives out of 20.
+  pub fn format_twenty(&self, rating: i64) -> String {
+    self.format(rating as f64 * 5.0)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DigestFrequency {
+  Daily,
+  /// Sent on Mondays.
+  Weekly,
+}
+
+impl DigestFrequency {
+  /// Time between two digests.
+  pub fn period(&self) -> Duration {
+    match *self {
+      DigestFrequency::Daily => Duration::days(1),
+      DigestFrequency::Weekly => Duration::weeks(1),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+  pub page_size: u32,
+  /// `None` follows the preference of the Kitsu account.
+  pub title_language: Option<TitleLanguagePreference>,
+  /// IANA name of the user's timezone, like `Europe/Berlin`. `None` is
+  /// UTC, with times shown as relative ones.
+  pub timezone: Option<String>,
+  /// Minutes east of UTC, picked before timezones could be. Only read, to
+  /// be turned into a timezone.
+  #[serde(skip_serializing)]
+  utc_offset: Option<i32>,
+  /// `None` shows ratings in percent, as Kitsu gives them, and leaves out
+  /// the user's own.
+  pub rating_scale: Option<RatingScale>,
+  pub notify_episodes: bool,
+  pub notify_digest: bool,
+  pub digest_frequency: DigestFrequency,
+  /// Local hour the digest is sent at.
+  pub digest_hour: u32,
+  pub notify_reminders: bool,
+  pub reminder_days: u32,
+  pub compact: bool,
+}
+
+impl Default for Settings {
+  fn default() -> Settings {
+    Settings {
+      page_size: PAGE_SIZES[0],
+      title_language: None,
+      timezone: None,
+      utc_offset: None,
+      rating_scale: None,
+      notify_episodes: false,
+      notify_digest: false,
+      digest_frequency: DigestFrequency::Daily,
+      digest_hour: 9,
+      notify_reminders: false,
+      reminder_days: REMINDER_PERIODS[1],
+      compact: false,
+    }
+  }
+}
+
+impl Settings {
+  /// The user's timezone. It's looked up each time, so daylight saving
+  /// time follows the rules of the date at hand.
+  pub fn zone(&self) -> Tz {
+    self
+      .timezone
+      .as_ref()
+      .and_then(|name| parse_timezone(name))
+      .unwrap_or(Tz::UTC)
+  }
+
+  /// Formats the time in the user's timezone.
+  pub fn local_time(&self, time: &DateTime<Utc>) -> String {
+    time
+      .with_timezone(&self.zone())
+      .format("%Y-%m-%d %H:%M")
+      .to_string()
+  }
+
+  /// The latest time a digest was due, at or before `now`.
+  pub fn digest_due(&self, now: &DateTime<Utc>) -> DateTime<Utc> {
+    let zone = self.zone();
+    let local = now.with_timezone(&zone);
+    let mut date = local.date().naive_local();
+    if local.hour() < self.digest_hour {
+      date = date.pred();
+    }
+    if self.digest_frequency == DigestFrequency::Weekly {
+      while date.weekday() != Weekday::Mon {
+        date = date.pred();
+      }
+    }
+    let due = date.and_hms(self.digest_hour, 0, 0);
+    // the hour is skipped on the day clocks go forward, then it's an hour later
+    zone
+      .from_local_datetime(&due)
+      .earliest()
+      .or_else(|| zone.from_local_datetime(&(due + Duration::hours(1))).earliest())
+      .map_or_else(|| DateTime::from_utc(due, Utc), |due| due.with_timezone(&Utc))
+  }
+
+  /// Brings values edited by hand back in range, so they can't panic later,
+  /// and turns an old UTC offset into a timezone.
+  fn clamp(&mut self) {
+    if let Some(offset) = self.utc_offset.take() {
+      // `Etc/GMT` zones count the other way round and only whole hours
+      if self.timezone.is_none() && offset != 0 && offset % 60 == 0 {
+        self.timezone = Some(format!("Etc/GMT{:+}", -offset / 60));
+      }
+    }
+    if self.timezone.as_ref().map_or(false, |name| parse_timezone(name).is_none()) {
+      self.timezone = None;
+    }
+    self.digest_hour = self.digest_hour.min(23);
+    self.reminder_days = self.reminder_days.max(1);
+  }
+
+  pub fn apply(&mut self, change: &SettingChange) {
+    match *change {
+      SettingChange::PageSize(size) => self.page_size = size,
+      SettingChange::TitleLanguage(ref preference) => self.title_language = preference.clone(),
+      SettingChange::Timezone(ref name) => {
+        self.timezone = name.as_ref().and_then(|name| parse_timezone(name)).map(|zone| {
+          zone.name().to_owned()
+        })
+      }
+      SettingChange::RatingScale(scale) => self.rating_scale = scale,
+      SettingChange::NotifyEpisodes(on) => self.notify_episodes = on,
+      SettingChange::NotifyDigest(on) => self.notify_digest = on,
+      SettingChange::DigestFrequency(frequency) => self.digest_frequency = frequency,
+      SettingChange::DigestHour(hour) => self.digest_hour = hour % 24,
+      SettingChange::NotifyReminders(on) => self.notify_reminders = on,
+      SettingChange::ReminderDays(days) => self.reminder_days = days.max(1),
+      SettingChange::Compact(on) => self.compact = on,
+    }
+  }
+}
+
+/// Looks up a timezone by its IANA name.
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+  Tz::from_str(name.trim()).ok()
+}
+
+/// A change made from the settings menu. Buttons carry the new value rather
+/// than a toggle, so pressing an outdated menu twice does no harm.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SettingChange {
+  PageSize(u32),
+  TitleLanguage(Option<TitleLanguagePreference>),
+  /// IANA name of the timezone, or `None` for UTC.
+  Timezone(Option<String>),
+  RatingScale(Option<RatingScale>),
+  NotifyEpisodes(bool),
+  NotifyDigest(bool),
+  DigestFrequency(DigestFrequency),
+  DigestHour(u32),
+  NotifyReminders(bool),
+  ReminderDays(u32),
+  Compact(bool),
+}
+
+/// Settings of every user who changed them, keyed by telegram id, and the
+/// group chats where shared browsing is on, with who turned it on.
+#[derive(Clone)]
+pub struct SettingsStore {
+  path: PathBuf,
+  settings: Rc<RefCell<HashMap<i64, Settings>>>,
+  shared_path: PathBuf,
+  shared: Rc<RefCell<HashMap<i64, i64>>>,
+}
+
+impl SettingsStore {
+  /// Loads the settings saved at `path` and the shared chats saved at
+  /// `shared_path`, if any.
+  pub fn load(path: PathBuf, shared_path: PathBuf) -> SettingsStore {
+    let mut settings: HashMap<i64, Settings> = persist::load(&path, "settings");
+    for settings in settings.values_mut() {
+      settings.clamp();
+    }
+    let shared = persist::load(&shared_path, "shared chats");
+    SettingsStore {
+      path,
+      shared_path,
+      settings: Rc::new(RefCell::new(settings)),
+      shared: Rc::new(RefCell::new(shared)),
+    }
+  }
+
+  fn save(&self) {
+    persist::save(&self.path, &*self.settings.borrow(), "settings");
+  }
+
+  pub fn get(&self, telegram_id: i64) -> Settings {
+    self
+      .settings
+      .borrow()
+      .get(&telegram_id)
+      .cloned()
+      .unwrap_or_default()
+  }
+
+  /// Applies the change and returns the updated settings.
+  pub fn update(&self, telegram_id: i64, change: &SettingChange) -> Settings {
+    let settings = {
+      let mut all = self.settings.borrow_mut();
+      let settings = all.entry(telegram_id).or_default();
+      settings.apply(change);
+      settings.clone()
+    };
+    self.save();
+    settings
+  }
+
+  /// Whether anyone in the group chat may use the browsing buttons.
+  pub fn is_shared(&self, chat_id: i64) -> bool {
+    self.shared.borrow().contains_key(&chat_id)
+  }
+
+  pub fn set_shared(&self, chat_id: i64, telegram_id: i64, shared: bool) {
+    if shared {
+      self.shared.borrow_mut().insert(chat_id, telegram_id);
+    } else {
+      self.shared.borrow_mut().remove(&chat_id);
+    }
+    self.save_shared();
+  }
+
+  fn save_shared(&self) {
+    persist::save(&self.shared_path, &*self.shared.borrow(), "shared chats");
+  }
+
+  /// Forgets the user's settings, and turns shared browsing off where they
+  /// turned it on.
+  pub fn remove(&self, telegram_id: i64) {
+    let removed = self.settings.borrow_mut().remove(&telegram_id).is_some();
+    if removed {
+      self.save();
+    }
+    let shared = self.shared.borrow().len();
+    self
+      .shared
+      .borrow_mut()
+      .retain(|_, &mut owner| owner != telegram_id);
+    if self.shared.borrow().len() != shared {
+      self.save_shared();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{DateTime, TimeZone, Utc};
+
+  use serde_json::from_str;
+
+  use super::{DigestFrequency, Settings};
+
+  fn at(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+    Utc.ymd(y, m, d).and_hms(h, min, 0)
+  }
+
+  fn settings(timezone: &str, hour: u32, frequency: DigestFrequency) -> Settings {
+    Settings {
+      timezone: Some(timezone.to_owned()),
+      digest_hour: hour,
+      digest_frequency: frequency,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn digest_due_east_of_utc_across_midnight() {
+    // 09:00 in Tokyo is midnight UTC
+    let settings = settings("Asia/Tokyo", 9, DigestFrequency::Daily);
+    assert_eq!(settings.digest_due(&at(2018, 1, 1, 23, 30)), at(2018, 1, 1, 0, 0));
+    assert_eq!(settings.digest_due(&at(2018, 1, 2, 0, 30)), at(2018, 1, 2, 0, 0));
+  }
+
+  #[test]
+  fn digest_due_west_of_utc_across_midnight() {
+    // 22:00 in New York is 03:00 UTC the next day
+    let settings = settings("America/New_York", 22, DigestFrequency::Daily);
+    assert_eq!(settings.digest_due(&at(2018, 1, 2, 2, 30)), at(2018, 1, 1, 3, 0));
+    assert_eq!(settings.digest_due(&at(2018, 1, 2, 3, 30)), at(2018, 1, 2, 3, 0));
+  }
+
+  #[test]
+  fn weekly_digest_due_on_local_mondays() {
+    // 2018-01-08 is a Monday, which starts at 15:00 UTC the day before in Tokyo
+    let settings = settings("Asia/Tokyo", 9, DigestFrequency::Weekly);
+    assert_eq!(settings.digest_due(&at(2018, 1, 7, 23, 30)), at(2018, 1, 1, 0, 0));
+    assert_eq!(settings.digest_due(&at(2018, 1, 8, 0, 30)), at(2018, 1, 8, 0, 0));
+  }
+
+  #[test]
+  fn digest_due_follows_daylight_saving_time() {
+    let settings = settings("Europe/Berlin", 9, DigestFrequency::Daily);
+    assert_eq!(settings.digest_due(&at(2018, 1, 15, 12, 0)), at(2018, 1, 15, 8, 0));
+    assert_eq!(settings.digest_due(&at(2018, 7, 15, 12, 0)), at(2018, 7, 15, 7, 0));
+    // 02:00 doesn't exist on the day clocks go forward
+    let settings = Settings {
+      digest_hour: 2,
+      ..settings
+    };
+    assert_eq!(settings.digest_due(&at(2018, 3, 25, 2, 0)), at(2018, 3, 25, 1, 0));
+  }
+
+  #[test]
+  fn old_utc_offsets_become_timezones() {
+    let mut settings: Settings = from_str(r#"{ "utc_offset": 120 }"#).unwrap();
+    settings.clamp();
+    assert_eq!(settings.timezone, Some(String::from("Etc/GMT-2")));
+    assert_eq!(settings.local_time(&at(2018, 1, 1, 0, 0)), "2018-01-01 02:00");
+
+    // half hours have no such zone
+    let mut settings: Settings = from_str(r#"{ "utc_offset": 330 }"#).unwrap();
+    settings.clamp();
+    assert_eq!(settings.timezone, None);
+
+    let mut settings: Settings = from_str(r#"{ "timezone": "Nowhere/Special" }"#).unwrap();
+    settings.clamp();
+    assert_eq!(settings.timezone, None);
+  }
+}
@@ -0,0 +1,261 @@
+use std::rc::Rc;
+use std::fs::File;
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use serde_json::from_reader;
+
+/// Built-in templates, giving the usual messages. `{name}` is replaced by
+/// the variable of that name; unknown variables are left as they are.
+const DEFAULTS: &'static [(&'static str, &'static str)] = &[
+  (
+    "anime_detail",
+    "<b>{title}</b> <i>{japanese_title}</i>\n\
+     <b>Subtype</b>: {subtype} <b>Status</b>: {anime_status}\n\
+     <b>Rating</b>: {rating} <b>Age Rating</b>: {age_rating}\n\
+     <b>Genres</b>: {genres}\n\
+     <b>Aired</b>: {start_date} ~ {end_date}\n\
+     <b>Progress</b>: {status}{rewatching} [{progress}/{episode_count}]\n\
+     <b>Rewatched</b>: {rewatch_count} times{private}\n\
+     <b>Updated</b>: {updated}",
+  ),
+  ("anime_detail_updated", "{updated_ago} ago"),
+  ("anime_detail_updated_local", "{updated_at} ({updated_ago} ago)"),
+  ("anime_detail_rating", "\n<b>Your Rating</b>: {rating}"),
+  ("anime_detail_notes", "\n<b>Notes</b>: {notes}"),
+  ("anime_not_found", "Error: No Anime Found :("),
+  (
+    "list_entry",
+    "<b>{index}| {title}</b> <i>{japanese_title}</i>\n\
+     {status} [{progress}/{episode_count}] updated {updated_ago} ago\n\n",
+  ),
+  (
+    "list_entry_compact",
+    "<b>{index}| {title}</b> [{progress}/{episode_count}]\n",
+  ),
+  ("list_entry_missing", "<b>{index}|</b> can't get attributes :(\n"),
+  ("list_footer", "<i>Choose an anime from the list above.</i>"),
+  ("non_registered", "Non-registered user: {user_id}"),
+  ("unknown_command", "Unknown command."),
+  (
+    "maintenance",
+    "Sagiri is under maintenance, please try again later.",
+  ),
+  ("non_registered_alert", "Non-registered user"),
+  ("admin_only", "Sorry, only admins can do that."),
+  ("button_expired", "This button has expired."),
+  ("button_not_owner", "These buttons belong to someone else."),
+  (
+    "version",
+    "<pre>Sagiri-{version}\nFor more information, please visit the wiki.</pre>",
+  ),
+  ("anime_unknown_title", "this anime"),
+  ("trending_heading", "Trending Anime"),
+  ("season_heading", "{season} {year}"),
+  ("plan_added", "Added to your planned list"),
+  ("plan_failed", "Couldn't add it, is it already in your library?"),
+  (
+    "progress_prompt",
+    "{mention}, which episode of <b>{title}</b> are you on? (0-{episode_count})",
+  ),
+  (
+    "progress_prompt_open",
+    "{mention}, which episode of <b>{title}</b> are you on?",
+  ),
+  ("progress_not_a_number", "Please reply with an episode number."),
+  ("progress_negative", "Episode number can't be negative."),
+  ("progress_too_high", "This anime only has {episode_count} episodes."),
+  ("progress_no_match", "No anime matching \"{title}\" in your library."),
+  ("progress_ambiguous", "Which one do you mean?\n{titles}"),
+  ("progress_updated", "Successful update to episode {progress}"),
+  ("notes_prompt", "{mention}, reply with the new notes for this entry."),
+  ("notes_saved", "Notes saved"),
+  ("entry_private", "This entry is now private"),
+  ("entry_public", "This entry is now public"),
+  ("rewatch_started", "Started rewatching, progress reset to episode 0"),
+  ("status_changed", "Status changed to {status}"),
+  ("muted", "You won't be notified about this anime anymore."),
+  ("unmuted", "You'll be notified about this anime again."),
+  ("export_done", "{count} entries exported"),
+  ("import_expired", "This import has expired, please send the file again."),
+  ("import_cancelled", "Import cancelled."),
+  ("import_started", "Import started"),
+  ("import_progress", "Importing... {done}/{total}"),
+  ("import_finished", "Import finished: {imported} imported, {failed} failed."),
+  ("use_done", "Now using {name}."),
+  ("use_unknown", "No linked account named {name}, see /accounts."),
+  ("settings_saved", "Settings saved."),
+  ("timezone_set", "Your timezone is now {timezone}, where it's {time}."),
+  (
+    "timezone_unknown",
+    "Unknown timezone {timezone}, try a name like Europe/Berlin.",
+  ),
+  ("shared_private_chat", "Shared browsing only applies to group chats."),
+  (
+    "shared_status",
+    "Shared browsing is {status} in this chat. Use /shared on or /shared off to change it.",
+  ),
+  ("shared_on", "Anyone in this chat can now browse others' lists."),
+  ("shared_off", "Buttons in this chat now only work for whoever requested them."),
+  ("shared_not_admin", "Only group admins can change this."),
+  (
+    "unregister_confirm",
+    "This unlinks your Kitsu account(s) from Sagiri, revokes the tokens it was \
+     given and deletes everything it keeps about you. Continue?",
+  ),
+  ("unregister_cancelled", "Nothing was deleted."),
+  (
+    "unregister_done",
+    "Your Kitsu account(s) were unlinked and your data deleted. Bye!",
+  ),
+  (
+    "unregister_kept",
+    "Your data here was deleted and your Kitsu token(s) revoked, but this \
+     bot's user store can't delete accounts, so your Telegram ID and Kitsu \
+     account(s) are still listed there. Please ask its operator to remove them.",
+  ),
+  (
+    "unregister_failed",
+    "Your data here was deleted and your Kitsu token(s) revoked, but your \
+     Telegram ID and Kitsu account(s) couldn't be removed from the user \
+     store. Please run /unregister again later.",
+  ),
+  (
+    "unregister_not_revoked",
+    "\nSome tokens couldn't be revoked at Kitsu, \
+     you may want to change your Kitsu password.",
+  ),
+  ("update_done", "<pre>Successful update: {count} user(s)</pre>"),
+  ("ban_admin", "Admins can't be banned."),
+  ("ban_done", "Banned {user_id}."),
+  ("ban_already", "{user_id} is already banned."),
+  ("unban_done", "Unbanned {user_id}."),
+  ("unban_not_banned", "{user_id} isn't banned."),
+  ("broadcast_done", "Broadcast sent to {sent}/{total} users."),
+  (
+    "maintenance_on",
+    "Maintenance mode is on, only admins can use sagiri now.",
+  ),
+  ("maintenance_off", "Maintenance mode is off."),
+  ("rotate_done", "Re-encrypted {rotated} token(s) with the current key."),
+  ("rotate_failed", "\n{failed} token(s) couldn't be decrypted by any key."),
+  (
+    "rotate_unsaved",
+    "\nThe user store is read-only, so this only applies until exit.",
+  ),
+];
+
+/// Named message templates. Operators can override any of them from a JSON
+/// file mapping names to templates.
+///
+/// Anime details, anime lists and every reply to a command, button or
+/// prompt are templated. The `/jobs` and `/users` listings, the settings
+/// menu, history, accounts, chart entries, undo, import summaries, digests,
+/// reminders, episode notifications and button labels keep their built-in
+/// text.
+#[derive(Clone)]
+pub struct Templates {
+  templates: Rc<HashMap<String, String>>,
+}
+
+impl Templates {
+  /// Loads the defaults, overridden by the templates at `path`, if any.
+  pub fn load(path: Option<PathBuf>) -> Templates {
+    let mut templates: HashMap<String, String> = DEFAULTS
+      .iter()
+      .map(|&(name, template)| (name.to_owned(), template.to_owned()))
+      .collect();
+    if let Some(path) = path {
+      let overrides: Result<HashMap<String, String>, String> = File::open(&path)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|file| from_reader(file).map_err(|e| format!("{:?}", e)));
+      match overrides {
+        Ok(overrides) => for (name, template) in overrides {
+          match templates.get_mut(&name) {
+            Some(default) => *default = template,
+            None => warn!("ignored unknown template: {:?}", name),
+          }
+        },
+        Err(e) => error!("failed to load templates, using the defaults: {}", e),
+      }
+    }
+    Templates {
+      templates: Rc::new(templates),
+    }
+  }
+
+  pub fn render(&self, name: &str, vars: &[(&str, &str)]) -> String {
+    match self.templates.get(name) {
+      Some(template) => fill(template, vars),
+      None => {
+        error!("no such template: {:?}", name);
+        String::new()
+      }
+    }
+  }
+}
+
+fn fill(template: &str, vars: &[(&str, &str)]) -> String {
+  let mut text = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    text.push_str(&rest[..start]);
+    let after = &rest[start + 1..];
+    let var = after.find('}').and_then(|end| {
+      vars
+        .iter()
+        .find(|&&(name, _)| name == &after[..end])
+        .map(|&(_, value)| (value, end))
+    });
+    match var {
+      Some((value, end)) => {
+        text.push_str(value);
+        rest = &after[end + 1..];
+      }
+      None => {
+        text.push('{');
+        rest = after;
+      }
+    }
+  }
+  text.push_str(rest);
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use super::fill;
+
+  #[test]
+  fn fills_known_variables() {
+    let text = fill("{index}| {title} [{progress}/{episode_count}]", &[
+      ("index", "1"),
+      ("title", "Eromanga Sensei"),
+      ("progress", "3"),
+      ("episode_count", "12"),
+    ]);
+    assert_eq!(text, "1| Eromanga Sensei [3/12]");
+  }
+
+  #[test]
+  fn fills_a_variable_used_twice() {
+    assert_eq!(fill("{a} and {a}", &[("a", "x")]), "x and x");
+  }
+
+  #[test]
+  fn leaves_unknown_variables() {
+    assert_eq!(fill("{a} {b}", &[("a", "x")]), "x {b}");
+  }
+
+  #[test]
+  fn leaves_unclosed_and_nested_braces() {
+    assert_eq!(fill("{a", &[("a", "x")]), "{a");
+    assert_eq!(fill("{{a}}", &[("a", "x")]), "{x}");
+    assert_eq!(fill("}{a}{", &[("a", "x")]), "}x{");
+  }
+
+  #[test]
+  fn does_not_fill_values_again() {
+    assert_eq!(fill("{a}{b}", &[("a", "{b}"), ("b", "y")]), "{b}y");
+  }
+}
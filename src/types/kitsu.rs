@@ -1,15 +1,43 @@
+use std::collections::HashMap;
+
 use chrono::prelude::{DateTime, Utc};
 
-#[serde(untagged)]
+/// A JSON:API document whose primary data is a `T`. Each endpoint is read
+/// as the document it returns, so an empty list can't pass for another one.
 #[derive(Debug, Serialize, Deserialize)]
-pub enum Json {
-  AnimeEntry {
-    data: Vec<Entry>,
-    links: Links,
-    included: Vec<Anime>,
-  },
-  Entry { data: Entry },
-  Error { errors: Vec<ApiError> },
+pub struct Document<T> {
+  pub data: T,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")] pub included: Vec<Included>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub links: Option<Links>,
+}
+
+impl<T> Document<T> {
+  pub fn new(data: T) -> Document<T> {
+    Document {
+      data,
+      included: Vec::new(),
+      links: None,
+    }
+  }
+
+  /// The included anime, in no particular order.
+  pub fn included_anime(self) -> (T, Vec<Anime>) {
+    let animes = self.included.into_iter().filter_map(|i| i.into_anime()).collect();
+    (self.data, animes)
+  }
+
+  pub fn prev_next(&self) -> (Option<String>, Option<String>) {
+    self
+      .links
+      .as_ref()
+      .map_or((None, None), |links| (links.prev.clone(), links.next.clone()))
+  }
+}
+
+/// What Kitsu answers instead of a document when a request fails.
+#[derive(Debug, Deserialize)]
+pub struct Errors {
+  pub errors: Vec<ApiError>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,23 +52,97 @@ pub enum Type {
   Anime,
   Users,
   Manga,
+  Genres,
+  Mappings,
   LibraryEntries,
+  LibraryEvents,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Included {
+  Anime(Anime),
+  Mappings(Mapping),
+}
+
+impl Included {
+  pub fn into_anime(self) -> Option<Anime> {
+    match self {
+      Included::Anime(anime) => Some(anime),
+      _ => None,
+    }
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Anime {
   #[serde(default = "String::new")] pub id: String,
   pub attributes: Option<AnimeAttributes>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub relationships: Option<AnimeRelationships>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnimeRelationships {
+  pub mappings: Option<ToMany>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToOne {
+  #[serde(default)] pub data: Option<Identifier>,
+}
+
+impl ToOne {
+  pub fn new(kind: Type, id: String) -> ToOne {
+    ToOne { data: Some(Identifier { id, kind }) }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToMany {
+  #[serde(default = "Vec::new")] pub data: Vec<Identifier>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Identifier {
+  pub id: String,
+  #[serde(rename = "type")] pub kind: Type,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Mapping {
+  pub id: String,
+  pub attributes: MappingAttributes,
+  pub relationships: Option<MappingRelationships>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MappingRelationships {
+  pub item: Option<ToOne>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingAttributes {
+  pub external_site: String,
+  pub external_id: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AnimeAttributes {
   pub canonical_title: String,
+  pub abbreviated_titles: Option<Vec<String>>,
   pub episode_count: Option<u32>,
   pub status: Option<AnimeStatus>,
   pub subtype: Option<AnimeSubtype>,
   pub titles: AnimeTitles,
+  pub synopsis: Option<String>,
+  pub average_rating: Option<String>,
+  pub start_date: Option<String>,
+  pub end_date: Option<String>,
+  pub age_rating: Option<AgeRating>,
+  pub age_rating_guide: Option<String>,
+  pub poster_image: Option<Image>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -65,14 +167,89 @@ pub enum AnimeStatus {
   Unknown,
 }
 
+impl AnimeAttributes {
+  /// Picks the title matching the user's preference, falling back to
+  /// other titles when the preferred one is missing.
+  pub fn title(&self, preference: &TitleLanguagePreference) -> &str {
+    let titles = &self.titles;
+    let candidates = match *preference {
+      TitleLanguagePreference::Canonical => vec![],
+      TitleLanguagePreference::English => vec![&titles.en, &titles.en_us, &titles.en_jp],
+      TitleLanguagePreference::Romanized => vec![&titles.en_jp, &titles.en, &titles.en_us],
+    };
+    candidates
+      .into_iter()
+      .filter_map(|title| title.as_ref())
+      .find(|title| !title.is_empty())
+      .map(|title| title.as_str())
+      .unwrap_or(&self.canonical_title)
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AgeRating {
+  G,
+  PG,
+  R,
+  R18,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Image {
+  pub tiny: Option<String>,
+  pub small: Option<String>,
+  pub medium: Option<String>,
+  pub large: Option<String>,
+  pub original: Option<String>,
+}
+
+impl Image {
+  /// Largest available size that is still sensible to send to Telegram.
+  pub fn url(&self) -> Option<&String> {
+    self
+      .large
+      .as_ref()
+      .or(self.medium.as_ref())
+      .or(self.original.as_ref())
+      .or(self.small.as_ref())
+  }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AnimeTitles {
+  pub en: Option<String>,
+  pub en_us: Option<String>,
+  pub en_jp: Option<String>,
   pub ja_jp: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Episode {
+  pub id: String,
+  pub attributes: EpisodeAttributes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EpisodeAttributes {
+  pub number: i64,
+  /// Formatted as `YYYY-MM-DD`.
+  pub airdate: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Genre {
+  pub id: String,
+  pub attributes: GenreAttributes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenreAttributes {
+  pub name: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct User {
-  id: i32,
+  pub id: String,
   pub attributes: UserAttributes,
 }
 
@@ -80,28 +257,79 @@ pub struct User {
 #[serde(rename_all = "camelCase")]
 pub struct UserAttributes {
   pub name: String,
-  pub life_spent_on_anime: i32,
-  pub title_language_preference: String,
+  #[serde(default)] pub title_language_preference: TitleLanguagePreference,
 }
 
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleLanguagePreference {
+  #[default]
+  Canonical,
+  English,
+  Romanized,
+}
+
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Entry {
-  pub id: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")] pub id: String,
   #[serde(rename = "type")] pub kind: Type,
   pub attributes: Option<EntryAttributes>,
   pub relationships: Option<Relationships>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Entry {
+  pub fn anime_id(&self) -> Option<&str> {
+    self
+      .relationships
+      .as_ref()
+      .and_then(|r| r.anime.as_ref())
+      .and_then(|anime| anime.data.as_ref())
+      .map(|anime| anime.id.as_str())
+  }
+}
+
+/// Pairs each entry with its anime, by the entry's relationship. Included
+/// anime are de-duplicated and unordered, so they can't be paired by
+/// position; entries whose anime is missing are dropped.
+pub fn pair_anime(entries: Vec<Entry>, animes: Vec<Anime>) -> Vec<(Entry, Anime)> {
+  let mut animes: HashMap<String, Anime> =
+    animes.into_iter().map(|anime| (anime.id.clone(), anime)).collect();
+  entries
+    .into_iter()
+    .filter_map(|entry| {
+      // the same anime can only be in a library once
+      let anime = entry.anime_id().and_then(|id| animes.remove(id));
+      anime.map(|anime| (entry, anime))
+    })
+    .collect()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct EntryAttributes {
   #[serde(skip_serializing_if = "Option::is_none")] pub progress: Option<i64>,
   #[serde(skip_serializing_if = "Option::is_none")] pub status: Option<EntryStatus>,
   #[serde(rename = "updatedAt")]
   #[serde(skip_serializing_if = "Option::is_none")]
   pub updated_at: Option<DateTime<Utc>>,
+  #[serde(rename = "startedAt")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub started_at: Option<DateTime<Utc>>,
+  #[serde(rename = "finishedAt")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub finished_at: Option<DateTime<Utc>>,
+  #[serde(rename = "ratingTwenty")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub rating_twenty: Option<i64>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub reconsuming: Option<bool>,
+  #[serde(rename = "reconsumeCount")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reconsume_count: Option<i64>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub notes: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub private: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum EntryStatus {
   OnHold,
@@ -113,22 +341,56 @@ pub enum EntryStatus {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Meta {
-  pub count: i32,
-  pub status_counts: MetaStatusCounts,
+pub struct LibraryEvent {
+  pub id: String,
+  pub attributes: LibraryEventAttributes,
+  pub relationships: Option<LibraryEventRelationships>,
+}
+
+impl LibraryEvent {
+  pub fn anime_id(&self) -> Option<&str> {
+    self
+      .relationships
+      .as_ref()
+      .and_then(|r| r.anime.as_ref())
+      .and_then(|anime| anime.data.as_ref())
+      .map(|anime| anime.id.as_str())
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LibraryEventRelationships {
+  pub anime: Option<ToOne>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct MetaStatusCounts {
-  current: Option<i32>,
-  dropped: Option<i32>,
-  on_hold: Option<i32>,
-  planned: Option<i32>,
-  completed: Option<i32>,
+pub struct LibraryEventAttributes {
+  pub kind: LibraryEventKind,
+  #[serde(default)] pub changed_data: ChangedData,
+  pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LibraryEventKind {
+  Progressed,
+  Updated,
+  Rated,
+  Reacted,
+  Annotated,
+}
+
+/// What an event changed, as `(before, after)` pairs.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ChangedData {
+  pub progress: Option<(Option<i64>, Option<i64>)>,
+  pub status: Option<(Option<EntryStatus>, Option<EntryStatus>)>,
+  /// Out of 20, like `ratingTwenty`.
+  pub rating: Option<(Option<i64>, Option<i64>)>,
+}
+
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Links {
   pub prev: Option<String>,
@@ -137,5 +399,6 @@ pub struct Links {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Relationships {
-  pub anime: Option<Anime>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub anime: Option<ToOne>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub user: Option<ToOne>,
 }
@@ -2,23 +2,38 @@ pub mod kitsu;
 pub mod matrix;
 pub mod telegram;
 
-use hyper_tls::HttpsConnector;
+use std::fmt;
+
+use hyper_openssl::HttpsConnector;
+
+use settings::SettingChange;
+use self::kitsu::EntryStatus;
 use hyper::client::{self, HttpConnector};
 
 pub type Client = client::Client<HttpsConnector<HttpConnector>>;
 
-#[serde(untagged)]
 #[derive(Debug, Deserialize)]
+#[serde(untagged)]
 pub enum DatabaseResponse {
   Ok { data: Vec<User> },
   Error { error: String },
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct User {
   pub kitsu_id: i64,
   pub telegram_id: i64,
-  pub kitsu_token: String,
+  pub kitsu_token: Secret,
+}
+
+/// A value kept out of `Debug` output, so it never ends up in the logs.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Secret(pub String);
+
+impl fmt::Debug for Secret {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Secret(..)")
+  }
 }
 
 #[derive(Debug)]
@@ -26,16 +41,217 @@ pub enum MsgCommand {
   List,
   Update,
   Version,
+  Export(ExportFormat),
+  Progress { title: String, progress: i64 },
+  Accounts,
+  Use(String),
+  Timezone(String),
+  Trending,
+  Season {
+    season: Option<Season>,
+    year: Option<i32>,
+  },
+  Shared(Option<bool>),
+  Users,
+  Ban(i64),
+  Unban(i64),
+  Broadcast(String),
+  Maintenance(bool),
+  RotateKey,
+  Jobs,
+  Unregister,
+  Settings,
+  History,
+}
+
+impl MsgCommand {
+  /// Whether the command is limited to admins.
+  pub fn is_operator(&self) -> bool {
+    match *self {
+      MsgCommand::Update |
+      MsgCommand::Users |
+      MsgCommand::Ban(_) |
+      MsgCommand::Unban(_) |
+      MsgCommand::Broadcast(_) |
+      MsgCommand::Maintenance(_) |
+      MsgCommand::RotateKey |
+      MsgCommand::Jobs => true,
+      _ => false,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Season {
+  Winter,
+  Spring,
+  Summer,
+  Fall,
+}
+
+impl Season {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      Season::Winter => "winter",
+      Season::Spring => "spring",
+      Season::Summer => "summer",
+      Season::Fall => "fall",
+    }
+  }
+
+  /// Season an (1-based) month falls in.
+  pub fn from_month(month: u32) -> Season {
+    match month {
+      1...3 => Season::Winter,
+      4...6 => Season::Spring,
+      7...9 => Season::Summer,
+      _ => Season::Fall,
+    }
+  }
 }
 
 #[derive(Debug)]
+pub enum ExportFormat {
+  Mal,
+  Csv,
+  Json,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum QueryCommand {
   Offset { kitsu_id: i64, offset: i64 },
-  Detail { kitsu_id: i64, anime_id: i64 },
+  History { kitsu_id: i64, offset: i64 },
+  Detail { kitsu_id: i64, anime_id: String },
   Progress {
     kitsu_id: i64,
     progress: i64,
     anime_id: String,
     entry_id: String,
   },
+  Import { kitsu_id: i64, start: bool },
+  Unregister { confirm: bool },
+  Setting(SettingChange),
+  SettingsDone,
+  Undo { kitsu_id: i64, change_id: u64 },
+  Season {
+    kitsu_id: i64,
+    season: Season,
+    year: i32,
+    offset: i64,
+  },
+  Plan { kitsu_id: i64, anime_id: String },
+  SetStatus {
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    status: EntryStatus,
+  },
+  Mute {
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    episode: i64,
+    muted: bool,
+  },
+  Rewatch {
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+  },
+  SetProgress {
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+  },
+  Notes {
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+  },
+  Private {
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    private: bool,
+  },
+}
+
+impl QueryCommand {
+  /// Whether the command only browses, so anyone in a chat with shared
+  /// browsing may use it.
+  pub fn is_browsing(&self) -> bool {
+    match *self {
+      QueryCommand::Offset { .. } |
+      QueryCommand::Detail { .. } |
+      QueryCommand::Season { .. } => true,
+      _ => false,
+    }
+  }
+}
+
+/// An inline keyboard button whose command is kept on our side; only a
+/// short key to look it up is sent to Telegram as `callback_data`.
+#[derive(Clone, Debug)]
+pub struct Button {
+  pub text: String,
+  pub command: QueryCommand,
+  /// Telegram user the button belongs to, if any.
+  pub owner: Option<i64>,
+}
+
+impl Button {
+  pub fn new(text: String, command: QueryCommand) -> Button {
+    Button {
+      text,
+      command,
+      owner: None,
+    }
+  }
+}
+
+pub type Keyboard = Vec<Vec<Button>>;
+
+/// Binds every button of the keyboard to `owner`.
+pub fn bind_keyboard(keyboard: Keyboard, owner: i64) -> Keyboard {
+  keyboard
+    .into_iter()
+    .map(|row| {
+      row
+        .into_iter()
+        .map(|button| Button {
+          owner: Some(owner),
+          ..button
+        })
+        .collect()
+    })
+    .collect()
+}
+
+/// How long a prompt waits for its answer, in minutes.
+pub const PROMPT_TTL_MINUTES: i64 = 30;
+
+/// A question sent with ForceReply, waiting for the user's answer.
+#[derive(Debug)]
+pub enum Prompt {
+  Progress {
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+    episode_count: Option<u32>,
+  },
+  Notes {
+    user_id: i64,
+    kitsu_id: i64,
+    anime_id: String,
+    entry_id: String,
+  },
+}
+
+impl Prompt {
+  pub fn user_id(&self) -> i64 {
+    match *self {
+      Prompt::Progress { user_id, .. } | Prompt::Notes { user_id, .. } => user_id,
+    }
+  }
 }
@@ -1,14 +1,17 @@
-#[serde(untagged)]
 #[derive(Debug, Deserialize)]
+#[serde(untagged)]
 pub enum Response {
   Bool { result: bool },
   Update { result: Vec<Update> },
+  File { result: File },
+  // must come before `Message`, whose fields are all optional
+  ChatMember { result: ChatMember },
   Message { result: Message },
   Error { description: String },
 }
 
-#[serde(untagged)]
 #[derive(Debug, Deserialize)]
+#[serde(untagged)]
 pub enum Update {
   Message { update_id: i32, message: Message },
   CallbackQuery {
@@ -24,11 +27,38 @@ pub struct Message {
   #[serde(skip_serializing_if = "Option::is_none")] pub date: Option<i32>,
   #[serde(skip_serializing_if = "Option::is_none")] pub chat: Option<Chat>,
   #[serde(skip_serializing_if = "Option::is_none")] pub text: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub photo: Option<Vec<PhotoSize>>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub caption: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub document: Option<Document>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub reply_to_message: Option<Box<Message>>,
   #[serde(skip_serializing_if = "Option::is_none")] pub chat_id: Option<i64>,
   #[serde(skip_serializing_if = "Option::is_none")] pub reply_markup: Option<ReplyMarkup>,
   #[serde(skip_serializing_if = "Option::is_none")] pub parse_mode: Option<ParseMode>,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PhotoSize {
+  pub file_id: String,
+  pub width: i32,
+  pub height: i32,
+  pub file_size: Option<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Document {
+  pub file_id: String,
+  pub file_name: Option<String>,
+  pub mime_type: Option<String>,
+  pub file_size: Option<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct File {
+  pub file_id: String,
+  pub file_size: Option<i32>,
+  pub file_path: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum ParseMode {
   HTML,
@@ -41,7 +71,9 @@ pub enum ReplyMarkup {
   InlineKeyboard(Vec<Vec<InlineKeyboardButton>>),
   ReplyKeyboardMarkup,
   ReplyKeyboardRemove,
-  ForceReply,
+  /// `selective` keeps the reply box to the users mentioned in the message.
+  #[serde(untagged)]
+  ForceReply { force_reply: bool, selective: bool },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -90,6 +122,23 @@ pub enum ChatType {
   Channel,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatMember {
+  pub user: User,
+  pub status: ChatMemberStatus,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatMemberStatus {
+  Creator,
+  Administrator,
+  Member,
+  Restricted,
+  Left,
+  Kicked,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CallbackQuery {
   pub id: String,
@@ -111,3 +160,29 @@ pub struct QueryAnswer {
   #[serde(skip_serializing_if = "Option::is_none")] pub show_alert: Option<bool>,
   pub callback_query_id: String,
 }
+
+#[derive(Serialize)]
+pub struct SendPhoto {
+  pub chat_id: i64,
+  pub photo: String,
+  #[serde(skip_serializing_if = "Option::is_none")] pub caption: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub parse_mode: Option<ParseMode>,
+  #[serde(skip_serializing_if = "Option::is_none")] pub reply_markup: Option<ReplyMarkup>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct DeleteMessage {
+  pub chat_id: i64,
+  pub message_id: i64,
+}
+
+#[derive(Serialize)]
+pub struct GetFile {
+  pub file_id: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct GetChatMember {
+  pub chat_id: i64,
+  pub user_id: i64,
+}
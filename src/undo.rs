@@ -0,0 +1,79 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use types::kitsu::EntryStatus;
+
+/// How long a change can be undone after it was made.
+pub const UNDO_WINDOW_MINUTES: i64 = 10;
+
+/// State of a library entry right before it was changed.
+#[derive(Debug)]
+pub struct Change {
+  pub user_id: i64,
+  pub kitsu_id: i64,
+  pub anime_id: String,
+  pub entry_id: String,
+  pub progress: Option<i64>,
+  pub status: Option<EntryStatus>,
+  pub reconsuming: Option<bool>,
+  pub reconsume_count: Option<i64>,
+  pub updated_at: Option<DateTime<Utc>>,
+  pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct UndoLog {
+  next_id: Rc<Cell<u64>>,
+  changes: Rc<RefCell<HashMap<u64, Change>>>,
+}
+
+impl UndoLog {
+  pub fn new() -> UndoLog {
+    UndoLog {
+      next_id: Rc::new(Cell::new(1)),
+      changes: Rc::new(RefCell::new(HashMap::new())),
+    }
+  }
+
+  fn is_expired(change: &Change) -> bool {
+    Utc::now().signed_duration_since(change.changed_at) > Duration::minutes(UNDO_WINDOW_MINUTES)
+  }
+
+  pub fn record(&self, change: Change) -> u64 {
+    let id = self.next_id.get();
+    self.next_id.set(id + 1);
+    let mut changes = self.changes.borrow_mut();
+    changes.retain(|_, change| !UndoLog::is_expired(change));
+    changes.insert(id, change);
+    id
+  }
+
+  /// Takes the change out of the log if it's still undoable by this user,
+  /// on the account it was made on.
+  pub fn take(&self, id: u64, user_id: i64, kitsu_id: i64) -> Option<Change> {
+    let mut changes = self.changes.borrow_mut();
+    match changes.get(&id) {
+      Some(change)
+        if change.user_id == user_id && change.kitsu_id == kitsu_id &&
+          !UndoLog::is_expired(change) => {}
+      _ => return None,
+    }
+    changes.remove(&id)
+  }
+
+  /// Puts a taken change back, so undoing it can be tried again.
+  pub fn restore(&self, id: u64, change: Change) {
+    self.changes.borrow_mut().insert(id, change);
+  }
+
+  /// Drops every change made by the user.
+  pub fn forget(&self, user_id: i64) {
+    self
+      .changes
+      .borrow_mut()
+      .retain(|_, change| change.user_id != user_id);
+  }
+}
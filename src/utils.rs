@@ -1,57 +1,294 @@
 use std::borrow::Cow;
 use std::str::FromStr;
+use std::collections::HashMap;
 
 use url::Url;
 
 use chrono::{Duration, Utc};
 
-use types::{MsgCommand, QueryCommand};
+use nom::{digit, IResult};
+
+use types::{Button, ExportFormat, Keyboard, MsgCommand, QueryCommand, Season};
+use undo::{Change, UNDO_WINDOW_MINUTES};
+use digest::Digest;
+use templates::Templates;
+use settings::{DigestFrequency, RatingScale, SettingChange, Settings, PAGE_SIZES,
+               REMINDER_PERIODS};
 use types::kitsu::*;
-use types::telegram::*;
+use types::kitsu::User;
 
 named!(pub parse_message<&str, MsgCommand>,
   alt!(
     map!(tag!("/list"), |_| MsgCommand::List) |
     map!(tag!("/update"), |_| MsgCommand::Update) |
-    map!(tag!("/version"), |_| MsgCommand::Version)
+    map!(tag!("/version"), |_| MsgCommand::Version) |
+    do_parse!(
+      tag!("/progress ") >>
+      args: map_opt!(rest_str, parse_progress_args) >>
+      (MsgCommand::Progress { title: args.0, progress: args.1 })
+    ) |
+    map!(tag!("/accounts"), |_| MsgCommand::Accounts) |
+    map!(tag!("/unregister"), |_| MsgCommand::Unregister) |
+    map!(tag!("/settings"), |_| MsgCommand::Settings) |
+    map!(tag!("/history"), |_| MsgCommand::History) |
+    map!(tag!("/users"), |_| MsgCommand::Users) |
+    map!(tag!("/rotatekey"), |_| MsgCommand::RotateKey) |
+    map!(tag!("/jobs"), |_| MsgCommand::Jobs) |
+    do_parse!(
+      tag!("/ban ") >>
+      id: map_res!(digit, i64::from_str) >>
+      (MsgCommand::Ban(id))
+    ) |
+    do_parse!(
+      tag!("/unban ") >>
+      id: map_res!(digit, i64::from_str) >>
+      (MsgCommand::Unban(id))
+    ) |
+    do_parse!(
+      tag!("/broadcast ") >>
+      text: map!(rest_str, |text: &str| text.trim().to_owned()) >>
+      (MsgCommand::Broadcast(text))
+    ) |
+    do_parse!(
+      tag!("/maintenance ") >>
+      on: alt!(
+        map!(tag!("on"), |_| true) |
+        map!(tag!("off"), |_| false)
+      ) >>
+      (MsgCommand::Maintenance(on))
+    ) |
+    do_parse!(
+      tag!("/timezone ") >>
+      name: map!(rest_str, |name: &str| name.trim().to_owned()) >>
+      (MsgCommand::Timezone(name))
+    ) |
+    do_parse!(
+      tag!("/use ") >>
+      name: map!(rest_str, |name: &str| name.trim().to_owned()) >>
+      (MsgCommand::Use(name))
+    ) |
+    map!(tag!("/trending"), |_| MsgCommand::Trending) |
+    do_parse!(
+      tag!("/shared") >>
+      shared: opt!(complete!(preceded!(
+        tag!(" "),
+        alt!(
+          map!(tag!("on"), |_| true) |
+          map!(tag!("off"), |_| false)
+        )
+      ))) >>
+      (MsgCommand::Shared(shared))
+    ) |
+    do_parse!(
+      tag!("/season") >>
+      season: opt!(complete!(preceded!(tag!(" "), parse_season))) >>
+      year: opt!(complete!(preceded!(tag!(" "), map_res!(digit, i32::from_str)))) >>
+      (MsgCommand::Season { season, year })
+    ) |
+    do_parse!(
+      tag!("/export") >>
+      format: opt!(complete!(preceded!(
+        tag!(" "),
+        alt!(
+          map!(tag!("mal"), |_| ExportFormat::Mal) |
+          map!(tag!("csv"), |_| ExportFormat::Csv) |
+          map!(tag!("json"), |_| ExportFormat::Json)
+        )
+      ))) >>
+      (MsgCommand::Export(format.unwrap_or(ExportFormat::Mal)))
+    )
   )
 );
 
-named!(pub parse_query<&str, QueryCommand>,
-  do_parse!(
-    tag!("/") >>
-    kitsu_id: map_res!(take_until!("/"), i64::from_str)  >>
-    command: alt!(
-      do_parse!(
-        tag!("/offset/") >>
-        offset: map_res!(take_until!("/"), i64::from_str)  >>
-        (QueryCommand::Offset{ kitsu_id, offset })
-      ) |
-      do_parse!(
-        tag!("/detail/") >>
-        anime_id: map_res!(take_until!("/"), i64::from_str)  >>
-        (QueryCommand::Detail{ kitsu_id, anime_id })
-      ) |
-      do_parse!(
-        tag!("/progress/") >>
-        anime_id: map!(take_until!("/"), String::from)  >>
-        tag!("/") >>
-        entry_id: map!(take_until!("/"), String::from)  >>
-        tag!("/") >>
-        progress: map_res!(take_until!("/"), i64::from_str)  >>
-        (QueryCommand::Progress{ kitsu_id, anime_id, entry_id, progress })
-      )
-    ) >>
-    (command)
+named!(parse_season<&str, Season>,
+  alt!(
+    map!(tag!("winter"), |_| Season::Winter) |
+    map!(tag!("spring"), |_| Season::Spring) |
+    map!(tag!("summer"), |_| Season::Summer) |
+    map!(tag!("fall"), |_| Season::Fall)
   )
 );
 
-pub fn get_offset(url: Option<String>) -> Option<String> {
-  url.map_or(None, |x| match Url::parse(&x) {
+/// Takes whatever is left of the message.
+fn rest_str(input: &str) -> IResult<&str, &str> {
+  IResult::Done(&input[input.len()..], input)
+}
+
+fn parse_progress_args(args: &str) -> Option<(String, i64)> {
+  let mut parts = args.trim().rsplitn(2, ' ');
+  match (parts.next().map(i64::from_str), parts.next()) {
+    (Some(Ok(progress)), Some(title)) if !title.trim().is_empty() => {
+      Some((title.trim().to_owned(), progress))
+    }
+    _ => None,
+  }
+}
+
+/// Finds the entry whose anime title matches `query`. When there isn't
+/// exactly one good match, the candidate titles are returned instead.
+pub fn find_entry(
+  query: &str,
+  entries: Vec<Entry>,
+  animes: Vec<Anime>,
+) -> Result<(Entry, Anime), Vec<String>> {
+  let query = query.to_lowercase();
+  let mut animes: HashMap<String, Anime> = animes.into_iter().map(|a| (a.id.clone(), a)).collect();
+  let mut exact = Vec::new();
+  let mut partial = Vec::new();
+  for entry in entries {
+    let anime_id = match entry
+      .relationships
+      .as_ref()
+      .and_then(|r| r.anime.as_ref())
+      .and_then(|a| a.data.as_ref())
+    {
+      Some(anime) => anime.id.clone(),
+      None => continue,
+    };
+    let titles: Vec<String> = match animes.get(&anime_id).and_then(|a| a.attributes.as_ref()) {
+      Some(attr) => vec![
+        Some(&attr.canonical_title),
+        attr.titles.en.as_ref(),
+        attr.titles.en_us.as_ref(),
+        attr.titles.en_jp.as_ref(),
+        attr.titles.ja_jp.as_ref(),
+      ].into_iter()
+        .filter_map(|t| t.map(|t| t.to_lowercase()))
+        .collect(),
+      None => continue,
+    };
+    if titles.iter().any(|t| t == &query) {
+      exact.push((entry, anime_id));
+    } else if titles.iter().any(|t| t.contains(&query)) {
+      partial.push((entry, anime_id));
+    }
+  }
+  let mut candidates = if exact.is_empty() { partial } else { exact };
+  if candidates.len() == 1 {
+    let (entry, anime_id) = candidates.pop().unwrap();
+    let anime = animes.remove(&anime_id).unwrap();
+    Ok((entry, anime))
+  } else {
+    Err(
+      candidates
+        .iter()
+        .filter_map(|&(_, ref anime_id)| animes.get(anime_id))
+        .filter_map(|a| a.attributes.as_ref())
+        .map(|attr| attr.canonical_title.clone())
+        .collect(),
+    )
+  }
+}
+
+/// Checks an episode number against the anime's episode count.
+pub fn check_progress(
+  templates: &Templates,
+  progress: i64,
+  episode_count: Option<u32>,
+) -> Result<i64, String> {
+  match episode_count {
+    _ if progress < 0 => Err(templates.render("progress_negative", &[])),
+    Some(count) if progress > count as i64 => Err(templates.render(
+      "progress_too_high",
+      &[("episode_count", &count.to_string())],
+    )),
+    _ => Ok(progress),
+  }
+}
+
+pub fn parse_progress_update(
+  kitsu_id: i64,
+  templates: &Templates,
+  anime_id: &str,
+  progress: i64,
+  undo: Option<u64>,
+) -> (String, Keyboard) {
+  parse_entry_update(
+    kitsu_id,
+    anime_id,
+    templates.render("progress_updated", &[("progress", &progress.to_string())]),
+    undo,
+  )
+}
+
+pub fn parse_entry_update(
+  kitsu_id: i64,
+  anime_id: &str,
+  text: String,
+  undo: Option<u64>,
+) -> (String, Keyboard) {
+  let mut buttons = parse_back_buttons(kitsu_id, anime_id);
+  if let Some(change_id) = undo {
+    buttons.insert(
+      0,
+      vec![
+        Button::new("undo".to_owned(), QueryCommand::Undo { kitsu_id, change_id }),
+      ],
+    );
+  }
+  (text, buttons)
+}
+
+pub fn parse_undo(
+  kitsu_id: i64,
+  change: Option<Change>,
+) -> (String, Keyboard) {
+  match change {
+    None => (
+      format!(
+        "This change can't be undone anymore, changes can only be undone within {} minutes.",
+        UNDO_WINDOW_MINUTES
+      ),
+      vec![
+        vec![
+          Button::new(
+            "back to list".to_owned(),
+            QueryCommand::Offset { kitsu_id, offset: 0 },
+          ),
+        ],
+      ],
+    ),
+    Some(change) => {
+      let current = Utc::now();
+      (
+        format!(
+          "Restored to episode {} ({:?}), as last updated {} ago",
+          change.progress.unwrap_or(0),
+          change.status.as_ref().unwrap_or(&EntryStatus::Unknown),
+          parse_duration(current.signed_duration_since(change.updated_at.unwrap_or(current)))
+        ),
+        parse_back_buttons(kitsu_id, &change.anime_id),
+      )
+    }
+  }
+}
+
+fn parse_back_buttons(kitsu_id: i64, anime_id: &str) -> Keyboard {
+  vec![
+    vec![
+      Button::new(
+        "back to anime".to_owned(),
+        QueryCommand::Detail {
+          kitsu_id,
+          anime_id: anime_id.to_owned(),
+        },
+      ),
+    ],
+    vec![
+      Button::new(
+        "back to list".to_owned(),
+        QueryCommand::Offset { kitsu_id, offset: 0 },
+      ),
+    ],
+  ]
+}
+
+pub fn get_offset(url: Option<String>) -> Option<i64> {
+  url.and_then(|x| match Url::parse(&x) {
     Ok(url) => url
       .query_pairs()
       .find(|&(ref key, _)| key == &Cow::Borrowed("page[offset]"))
-      .map_or(None, |(_, offset)| Some(offset.to_string())),
+      .and_then(|(_, offset)| i64::from_str(&offset).ok()),
     _ => None,
   })
 }
@@ -68,122 +305,840 @@ pub fn parse_duration(duration: Duration) -> String {
   }
 }
 
+pub fn escape_html(text: &str) -> String {
+  text
+    .replace("&", "&amp;")
+    .replace("<", "&lt;")
+    .replace(">", "&gt;")
+}
+
+/// Links to the user by name, which also mentions them.
+pub fn mention(user_id: i64, name: &str) -> String {
+  format!("<a href=\"tg://user?id={}\">{}</a>", user_id, escape_html(name))
+}
+
+/// Cuts `text` down to at most `max` characters, marking the cut with dots.
+pub fn truncate(text: &str, max: usize) -> String {
+  if text.chars().count() > max {
+    let mut cut: String = text.chars().take(max.saturating_sub(3)).collect();
+    cut.push_str("...");
+    cut
+  } else {
+    text.to_owned()
+  }
+}
+
+/// Telegram refuses photo captions longer than this many characters.
+const CAPTION_LIMIT: usize = 1024;
+
 pub fn parse_anime_detail(
   kitsu_id: i64,
+  preference: &TitleLanguagePreference,
+  settings: &Settings,
+  templates: &Templates,
   pair: Option<(Entry, Anime)>,
-) -> (String, Vec<Vec<InlineKeyboardButton>>) {
+  genres: Vec<String>,
+) -> (Option<String>, String, Keyboard) {
   let current = Utc::now();
+  let mut poster = None;
   let mut buttons = Vec::new();
   let text = match pair {
-    None => format!("Error: No Anime Found :("),
+    None => templates.render("anime_not_found", &[]),
     Some((entry, anime)) => {
       let anime_attr = anime.attributes.unwrap();
       let entry_attr = entry.attributes.unwrap();
       buttons.push(vec![
-        InlineKeyboardButton::with_callback_data(
+        Button::new(
           format!("Make {} Complete", entry_attr.progress.unwrap_or(0) + 1),
-          format!(
-            "/{}/progress/{}/{}/{}/",
+          QueryCommand::Progress {
             kitsu_id,
-            anime.id,
-            entry.id,
-            entry_attr.progress.unwrap_or(0) + 1
-          ),
+            anime_id: anime.id.clone(),
+            entry_id: entry.id.clone(),
+            progress: entry_attr.progress.unwrap_or(0) + 1,
+          },
+        ),
+        Button::new(
+          String::from("Set Episode..."),
+          QueryCommand::SetProgress {
+            kitsu_id,
+            anime_id: anime.id.clone(),
+            entry_id: entry.id.clone(),
+          },
         ),
       ]);
-      format!(
-        "<b>Title</b>: {}\n\
-         <b>JapaneseTitle</b>: {}\n\
-         <b>Subtype</b>: {:?}\n\
-         <b>Status</b>: {:?}\n\
-         <b>Progress</b>: {:?} [{}/{}]\n\
-         <b>Updated</b>: {} ago",
-        anime_attr.canonical_title,
-        anime_attr.titles.ja_jp.unwrap_or(String::from("null")),
-        anime_attr.subtype.unwrap_or(AnimeSubtype::Unknown),
-        anime_attr.status.unwrap_or(AnimeStatus::Unknown),
-        entry_attr.status.unwrap_or(EntryStatus::Unknown),
-        entry_attr.progress.unwrap_or(0),
-        anime_attr.episode_count.unwrap_or(99),
-        parse_duration(current.signed_duration_since(entry_attr.updated_at.unwrap_or(current)))
-      )
+      let private = entry_attr.private == Some(true);
+      buttons.push(vec![
+        Button::new(
+          String::from("Edit Notes"),
+          QueryCommand::Notes {
+            kitsu_id,
+            anime_id: anime.id.clone(),
+            entry_id: entry.id.clone(),
+          },
+        ),
+        Button::new(
+          String::from(if private { "Make Public" } else { "Make Private" }),
+          QueryCommand::Private {
+            kitsu_id,
+            anime_id: anime.id.clone(),
+            entry_id: entry.id.clone(),
+            private: !private,
+          },
+        ),
+      ]);
+      let completed = entry_attr.status == Some(EntryStatus::Completed);
+      if completed && entry_attr.reconsuming != Some(true) {
+        buttons.push(vec![
+          Button::new(
+            String::from("Rewatch"),
+            QueryCommand::Rewatch {
+              kitsu_id,
+              anime_id: anime.id.clone(),
+              entry_id: entry.id.clone(),
+            },
+          ),
+        ]);
+      }
+      poster = anime_attr
+        .poster_image
+        .as_ref()
+        .and_then(|image| image.url())
+        .cloned();
+      let updated_at = entry_attr.updated_at.unwrap_or(current);
+      let updated_ago = parse_duration(current.signed_duration_since(updated_at));
+      let updated_at = settings.local_time(&updated_at);
+      let updated_vars = [
+        ("updated_at", updated_at.as_str()),
+        ("updated_ago", updated_ago.as_str()),
+      ];
+      // the time is only shown to users who picked their timezone
+      let updated = match settings.timezone {
+        None => templates.render("anime_detail_updated", &updated_vars),
+        Some(_) => templates.render("anime_detail_updated_local", &updated_vars),
+      };
+      let mut text = templates.render(
+        "anime_detail",
+        &[
+          ("title", &escape_html(anime_attr.title(preference))),
+          (
+            "japanese_title",
+            &anime_attr
+              .titles
+              .ja_jp
+              .as_ref()
+              .map_or(String::from("null"), |title| escape_html(title)),
+          ),
+          (
+            "subtype",
+            &format!("{:?}", anime_attr.subtype.as_ref().unwrap_or(&AnimeSubtype::Unknown)),
+          ),
+          (
+            "anime_status",
+            &format!("{:?}", anime_attr.status.as_ref().unwrap_or(&AnimeStatus::Unknown)),
+          ),
+          (
+            "rating",
+            &anime_attr
+              .average_rating
+              .as_ref()
+              .map_or(String::from("null"), |rating| {
+                settings.rating_scale.unwrap_or_default().format_average(rating)
+              }),
+          ),
+          (
+            "age_rating",
+            &anime_attr
+              .age_rating
+              .as_ref()
+              .map_or(String::from("null"), |rating| match anime_attr.age_rating_guide {
+                Some(ref guide) => format!("{:?} ({})", rating, guide),
+                None => format!("{:?}", rating),
+              }),
+          ),
+          (
+            "genres",
+            &if genres.is_empty() { String::from("null") } else { escape_html(&genres.join(", ")) },
+          ),
+          ("start_date", anime_attr.start_date.as_ref().map_or("?", |date| date.as_str())),
+          ("end_date", anime_attr.end_date.as_ref().map_or("?", |date| date.as_str())),
+          (
+            "status",
+            &format!("{:?}", entry_attr.status.as_ref().unwrap_or(&EntryStatus::Unknown)),
+          ),
+          (
+            "rewatching",
+            if entry_attr.reconsuming == Some(true) { " (Rewatching)" } else { "" },
+          ),
+          ("progress", &entry_attr.progress.unwrap_or(0).to_string()),
+          ("episode_count", &anime_attr.episode_count.unwrap_or(99).to_string()),
+          ("rewatch_count", &entry_attr.reconsume_count.unwrap_or(0).to_string()),
+          ("private", if private { " <b>Private</b>" } else { "" }),
+          ("updated", &updated),
+          updated_vars[0],
+          updated_vars[1],
+        ],
+      );
+      // and their own rating to users who picked a rating scale
+      match entry_attr.rating_twenty {
+        Some(rating) if settings.rating_scale.is_some() => text.push_str(&templates.render(
+          "anime_detail_rating",
+          &[("rating", &settings.rating_scale.unwrap_or_default().format_twenty(rating))],
+        )),
+        _ => (),
+      }
+      match entry_attr.notes {
+        Some(ref notes) if !notes.is_empty() => text.push_str(&templates.render(
+          "anime_detail_notes",
+          &[("notes", &escape_html(&truncate(notes, 200)))],
+        )),
+        _ => (),
+      }
+      if let Some(ref synopsis) = anime_attr.synopsis {
+        // tags don't count towards the caption limit, but keep it simple
+        let room = CAPTION_LIMIT.saturating_sub(text.chars().count() + 2);
+        if room > 3 {
+          text.push_str("\n\n");
+          text.push_str(&escape_html(&truncate(synopsis, room)));
+        }
+      }
+      text
     }
   };
   buttons.push(vec![
-    InlineKeyboardButton::with_callback_data(
+    Button::new(
       String::from("Back to List"),
-      format!("/{}/offset/0/", kitsu_id),
+      QueryCommand::Offset { kitsu_id, offset: 0 },
     ),
   ]);
-  (text, buttons)
+  (poster, text, buttons)
 }
 
 pub fn parse_anime_list(
   kitsu_id: i64,
+  preference: &TitleLanguagePreference,
+  settings: &Settings,
+  templates: &Templates,
   prev: Option<String>,
   next: Option<String>,
   entries: Vec<Entry>,
   animes: Vec<Anime>,
-) -> (String, Vec<Vec<InlineKeyboardButton>>) {
+) -> (String, Keyboard) {
   let current = Utc::now();
   let mut index = vec![];
   let mut navigate = vec![];
   if let Some(offset) = get_offset(prev) {
-    navigate.push(InlineKeyboardButton::with_callback_data(
+    navigate.push(Button::new(
       String::from("Prev"),
-      format!("/{}/offset/{}/", kitsu_id, offset),
+      QueryCommand::Offset { kitsu_id, offset },
     ))
   }
   if let Some(offset) = get_offset(next) {
-    navigate.push(InlineKeyboardButton::with_callback_data(
+    navigate.push(Button::new(
       String::from("Next"),
-      format!("/{}/offset/{}/", kitsu_id, offset),
+      QueryCommand::Offset { kitsu_id, offset },
     ))
   }
   let mut text = String::new();
   for (
     i,
     (
-      &Entry { attributes: ref entry_attr, .. },
-      &Anime {
-        id: ref anime_id,
-        attributes: ref anime_attr,
+      Entry { attributes: entry_attr, .. },
+      Anime {
+        id: anime_id,
+        attributes: anime_attr,
         ..
       },
     ),
-  ) in entries.iter().zip(animes.iter()).enumerate()
+  ) in pair_anime(entries, animes).into_iter().enumerate()
   {
-    match (entry_attr, anime_attr) {
+    match (&entry_attr, &anime_attr) {
+      (&Some(ref entry_attr), &Some(ref anime_attr)) if settings.compact => {
+        text.push_str(&templates.render(
+          "list_entry_compact",
+          &[
+            ("index", &i.to_string()),
+            ("title", &escape_html(anime_attr.title(preference))),
+            ("progress", &entry_attr.progress.unwrap_or(0).to_string()),
+            ("episode_count", &anime_attr.episode_count.unwrap_or(99).to_string()),
+          ],
+        ));
+        index.push(Button::new(
+          format!("{} {}", i, anime_attr.title(preference)),
+          QueryCommand::Detail {
+            kitsu_id,
+            anime_id: anime_id.clone(),
+          },
+        ));
+      }
       (&Some(ref entry_attr), &Some(ref anime_attr)) => {
+        let updated_at = entry_attr.updated_at.unwrap_or(current);
+        text.push_str(&templates.render(
+          "list_entry",
+          &[
+            ("index", &i.to_string()),
+            ("title", &escape_html(anime_attr.title(preference))),
+            (
+              "japanese_title",
+              &anime_attr
+                .titles
+                .ja_jp
+                .as_ref()
+                .map_or(String::from("null"), |title| escape_html(title)),
+            ),
+            (
+              "status",
+              &format!("{:?}", entry_attr.status.as_ref().unwrap_or(&EntryStatus::Unknown)),
+            ),
+            ("progress", &entry_attr.progress.unwrap_or(0).to_string()),
+            ("episode_count", &anime_attr.episode_count.unwrap_or(99).to_string()),
+            ("updated_ago", &parse_duration(current.signed_duration_since(updated_at))),
+          ],
+        ));
+        index.push(Button::new(
+          format!("{} {}", i, anime_attr.title(preference)),
+          QueryCommand::Detail {
+            kitsu_id,
+            anime_id: anime_id.clone(),
+          },
+        ));
+      }
+      _ => {
+        text.push_str(&templates.render("list_entry_missing", &[("index", &i.to_string())]));
+        index.push(Button::new(
+          format!("{} can't get title :(", i),
+          QueryCommand::Detail {
+            kitsu_id,
+            anime_id: anime_id.clone(),
+          },
+        ));
+      }
+    }
+  }
+  if settings.compact {
+    text.push('\n');
+  }
+  text.push_str(&templates.render("list_footer", &[]));
+  let mut buttons: Keyboard = index.chunks(4).map(|row| row.to_vec()).collect();
+  buttons.push(navigate);
+  (text, buttons)
+}
+
+fn parse_change<T, F: Fn(&T) -> String>(change: &(Option<T>, Option<T>), format: F) -> String {
+  let show = |value: &Option<T>| value.as_ref().map_or(String::from("none"), &format);
+  format!("{} → {}", show(&change.0), show(&change.1))
+}
+
+/// Renders a page of library events, newest first.
+pub fn parse_history(
+  kitsu_id: i64,
+  preference: &TitleLanguagePreference,
+  settings: &Settings,
+  prev: Option<String>,
+  next: Option<String>,
+  events: Vec<LibraryEvent>,
+  animes: Vec<Anime>,
+) -> (String, Keyboard) {
+  let current = Utc::now();
+  let mut navigate = vec![];
+  if let Some(offset) = get_offset(prev) {
+    navigate.push(Button::new(
+      String::from("Prev"),
+      QueryCommand::History { kitsu_id, offset },
+    ))
+  }
+  if let Some(offset) = get_offset(next) {
+    navigate.push(Button::new(
+      String::from("Next"),
+      QueryCommand::History { kitsu_id, offset },
+    ))
+  }
+  let animes: HashMap<&str, &AnimeAttributes> = animes
+    .iter()
+    .filter_map(|anime| anime.attributes.as_ref().map(|attr| (anime.id.as_str(), attr)))
+    .collect();
+  let mut text = String::new();
+  for event in &events {
+    let title = event
+      .anime_id()
+      .and_then(|id| animes.get(id))
+      .map_or(String::from("can't get title :("), |attr| {
+        escape_html(attr.title(preference))
+      });
+    let data = &event.attributes.changed_data;
+    let mut changes = vec![];
+    if let Some(ref progress) = data.progress {
+      changes.push(format!("Episode {}", parse_change(progress, |p| p.to_string())));
+    }
+    if let Some(ref status) = data.status {
+      changes.push(format!("Status {}", parse_change(status, |s| format!("{:?}", s))));
+    }
+    if let Some(ref rating) = data.rating {
+      changes.push(format!(
+        "Rating {}",
+        parse_change(rating, |r| settings.rating_scale.unwrap_or_default().format_twenty(*r))
+      ));
+    }
+    if changes.is_empty() {
+      changes.push(format!("{:?}", event.attributes.kind));
+    }
+    text.push_str(&format!(
+      "<b>{}</b>\n{}, {} ago\n\n",
+      title,
+      changes.join(", "),
+      parse_duration(current.signed_duration_since(event.attributes.created_at))
+    ));
+  }
+  if events.is_empty() {
+    text.push_str("<i>Nothing happened in your library yet.</i>");
+  }
+  (text, vec![navigate])
+}
+
+/// Tells the user about a new episode. Sent as plain text, so the message
+/// can be edited again without knowing the title.
+pub fn parse_new_episode(
+  kitsu_id: i64,
+  anime_id: &str,
+  entry_id: &str,
+  title: &str,
+  episode: i64,
+) -> (String, Keyboard) {
+  (
+    format!("Episode {} of {} is out.", episode, title),
+    parse_episode_buttons(kitsu_id, anime_id, entry_id, episode, false),
+  )
+}
+
+pub fn parse_episode_buttons(
+  kitsu_id: i64,
+  anime_id: &str,
+  entry_id: &str,
+  episode: i64,
+  muted: bool,
+) -> Keyboard {
+  vec![
+    vec![
+      Button::new(
+        String::from("Mark watched"),
+        QueryCommand::Progress {
+          kitsu_id,
+          progress: episode,
+          anime_id: anime_id.to_owned(),
+          entry_id: entry_id.to_owned(),
+        },
+      ),
+      Button::new(
+        String::from(if muted { "Unmute this anime" } else { "Mute this anime" }),
+        QueryCommand::Mute {
+          kitsu_id,
+          anime_id: anime_id.to_owned(),
+          entry_id: entry_id.to_owned(),
+          episode,
+          muted: !muted,
+        },
+      ),
+    ],
+  ]
+}
+
+/// Renders anime outside of the user's library, such as trending or
+/// seasonal charts. `season` enables paging through that season.
+pub fn parse_anime_chart(
+  kitsu_id: i64,
+  preference: &TitleLanguagePreference,
+  settings: &Settings,
+  heading: String,
+  season: Option<(Season, i32)>,
+  prev: Option<String>,
+  next: Option<String>,
+  animes: Vec<Anime>,
+) -> (String, Keyboard) {
+  let mut buttons = Vec::new();
+  let mut navigate = vec![];
+  if let Some((season, year)) = season {
+    if let Some(offset) = get_offset(prev) {
+      navigate.push(Button::new(
+        String::from("Prev"),
+        QueryCommand::Season {
+          kitsu_id,
+          season,
+          year,
+          offset,
+        },
+      ))
+    }
+    if let Some(offset) = get_offset(next) {
+      navigate.push(Button::new(
+        String::from("Next"),
+        QueryCommand::Season {
+          kitsu_id,
+          season,
+          year,
+          offset,
+        },
+      ))
+    }
+  }
+  let mut text = format!("<b>{}</b>\n\n", heading);
+  for (i, anime) in animes.iter().enumerate() {
+    match anime.attributes {
+      Some(ref anime_attr) => {
         text.push_str(&format!(
           "<b>{}| {}</b> <i>{}</i>\n\
-           {:?} [{}/{}] updated {} ago\n\n",
+           {:?} {} eps rated {}\n\n",
           i,
-          anime_attr.canonical_title,
+          escape_html(anime_attr.title(preference)),
           anime_attr
             .titles
             .ja_jp
             .as_ref()
-            .unwrap_or(&String::from("null")),
-          entry_attr.status.as_ref().unwrap_or(&EntryStatus::Unknown),
-          entry_attr.progress.unwrap_or(0),
-          anime_attr.episode_count.unwrap_or(99),
-          parse_duration(current.signed_duration_since(entry_attr.updated_at.unwrap_or(current)))
-        ));
-        index.push(InlineKeyboardButton::with_callback_data(
-          format!("{} {}", i, anime_attr.canonical_title),
-          format!("/{}/detail/{}/", kitsu_id, anime_id),
-        ));
-      }
-      _ => {
-        text.push_str(&format!("<b>{}|</b> can't get attributes :(\n", i));
-        index.push(InlineKeyboardButton::with_callback_data(
-          format!("{} can't get title :(", i),
-          format!("/{}/detail/{}/", kitsu_id, anime_id),
+            .map_or(String::from("null"), |title| escape_html(title)),
+          anime_attr.subtype.as_ref().unwrap_or(&AnimeSubtype::Unknown),
+          anime_attr
+            .episode_count
+            .map_or(String::from("?"), |count| count.to_string()),
+          anime_attr
+            .average_rating
+            .as_ref()
+            .map_or(String::from("null"), |rating| {
+              settings.rating_scale.unwrap_or_default().format_average(rating)
+            }),
         ));
+        buttons.push(vec![
+          Button::new(
+            format!("Add {} {} to Planned", i, anime_attr.title(preference)),
+            QueryCommand::Plan {
+              kitsu_id,
+              anime_id: anime.id.clone(),
+            },
+          ),
+        ]);
       }
+      None => text.push_str(&format!("<b>{}|</b> can't get attributes :(\n", i)),
+    }
+  }
+  if animes.is_empty() {
+    text.push_str("<i>Nothing here.</i>");
+  }
+  buttons.push(navigate);
+  (text, buttons)
+}
+
+pub fn parse_settings(settings: &Settings) -> (String, Keyboard) {
+  let on_off = |on: bool| if on { "on" } else { "off" };
+  let titles = |preference: &Option<TitleLanguagePreference>| match *preference {
+    None => "Kitsu account",
+    Some(TitleLanguagePreference::Canonical) => "Canonical",
+    Some(TitleLanguagePreference::English) => "English",
+    Some(TitleLanguagePreference::Romanized) => "Romanized",
+  };
+  let next_titles = match settings.title_language {
+    None => Some(TitleLanguagePreference::Canonical),
+    Some(TitleLanguagePreference::Canonical) => Some(TitleLanguagePreference::English),
+    Some(TitleLanguagePreference::English) => Some(TitleLanguagePreference::Romanized),
+    Some(TitleLanguagePreference::Romanized) => None,
+  };
+  let next_page_size = PAGE_SIZES
+    .iter()
+    .cloned()
+    .find(|&size| size > settings.page_size)
+    .unwrap_or(PAGE_SIZES[0]);
+  let ratings = |scale: Option<RatingScale>| match scale {
+    None => String::from("Kitsu"),
+    Some(scale) => scale.format_average("82.5"),
+  };
+  let next_ratings = match settings.rating_scale {
+    None => Some(RatingScale::Percent),
+    Some(RatingScale::Percent) => Some(RatingScale::Ten),
+    Some(RatingScale::Ten) => Some(RatingScale::Five),
+    Some(RatingScale::Five) => None,
+  };
+  let frequency = |frequency: DigestFrequency| match frequency {
+    DigestFrequency::Daily => "daily",
+    DigestFrequency::Weekly => "weekly",
+  };
+  let next_frequency = match settings.digest_frequency {
+    DigestFrequency::Daily => DigestFrequency::Weekly,
+    DigestFrequency::Weekly => DigestFrequency::Daily,
+  };
+  let next_reminder_days = REMINDER_PERIODS
+    .iter()
+    .cloned()
+    .find(|&days| days > settings.reminder_days)
+    .unwrap_or(REMINDER_PERIODS[0]);
+  let reminders = if settings.notify_reminders {
+    format!("after {} days", settings.reminder_days)
+  } else {
+    String::from("off")
+  };
+  let digests = if settings.notify_digest {
+    format!(
+      "{} at {:02}:00",
+      frequency(settings.digest_frequency),
+      settings.digest_hour
+    )
+  } else {
+    String::from("off")
+  };
+
+  let text = format!(
+    "<b>Settings</b>\n\
+     <b>Page size</b>: {}\n\
+     <b>Titles</b>: {}\n\
+     <b>Timezone</b>: {} (now {})\n\
+     <b>Ratings</b>: {}\n\
+     <b>List</b>: {}\n\
+     <b>New episode alerts</b>: {}\n\
+     <b>Digests</b>: {}\n\
+     <b>Reminders</b>: {}\n\n\
+     <i>Press a button to change a setting, or pick a timezone with \
+     /timezone Europe/Berlin.</i>",
+    settings.page_size,
+    titles(&settings.title_language),
+    settings.timezone.as_ref().map_or("not picked", |name| name.as_str()),
+    settings.local_time(&Utc::now()),
+    ratings(settings.rating_scale),
+    if settings.compact { "compact" } else { "verbose" },
+    on_off(settings.notify_episodes),
+    digests,
+    reminders,
+  );
+
+  let setting =
+    |text: String, change: SettingChange| Button::new(text, QueryCommand::Setting(change));
+  let mut buttons = vec![
+    vec![
+      setting(
+        format!("Page size: {}", next_page_size),
+        SettingChange::PageSize(next_page_size),
+      ),
+      setting(
+        format!("Titles: {}", titles(&next_titles)),
+        SettingChange::TitleLanguage(next_titles.clone()),
+      ),
+    ],
+    vec![
+      setting(
+        format!("Ratings: {}", ratings(next_ratings)),
+        SettingChange::RatingScale(next_ratings),
+      ),
+      setting(
+        String::from(if settings.compact { "Verbose list" } else { "Compact list" }),
+        SettingChange::Compact(!settings.compact),
+      ),
+    ],
+    vec![
+      setting(
+        format!("Episode alerts {}", on_off(!settings.notify_episodes)),
+        SettingChange::NotifyEpisodes(!settings.notify_episodes),
+      ),
+      setting(
+        format!("Digests {}", on_off(!settings.notify_digest)),
+        SettingChange::NotifyDigest(!settings.notify_digest),
+      ),
+      setting(
+        format!("Reminders {}", on_off(!settings.notify_reminders)),
+        SettingChange::NotifyReminders(!settings.notify_reminders),
+      ),
+    ],
+  ];
+  if settings.notify_digest {
+    buttons.push(vec![
+      setting(
+        format!("Send {}", frequency(next_frequency)),
+        SettingChange::DigestFrequency(next_frequency),
+      ),
+      setting(
+        String::from("Earlier"),
+        SettingChange::DigestHour((settings.digest_hour + 23) % 24),
+      ),
+      setting(
+        String::from("Later"),
+        SettingChange::DigestHour((settings.digest_hour + 1) % 24),
+      ),
+    ]);
+  }
+  if settings.timezone.is_some() {
+    buttons.push(vec![setting(String::from("Back to UTC"), SettingChange::Timezone(None))]);
+  }
+  if settings.notify_reminders {
+    buttons.push(vec![
+      setting(
+        format!("Remind after {} days", next_reminder_days),
+        SettingChange::ReminderDays(next_reminder_days),
+      ),
+    ]);
+  }
+  buttons.push(vec![Button::new(String::from("Done"), QueryCommand::SettingsDone)]);
+  (text, buttons)
+}
+
+/// Asks whether the user is still watching an entry they left alone.
+pub fn parse_reminder(
+  kitsu_id: i64,
+  anime_id: &str,
+  entry_id: &str,
+  title: &str,
+  idle: Duration,
+  progress: i64,
+  episode_count: Option<u32>,
+) -> (String, Keyboard) {
+  let text = format!(
+    "You haven't updated <b>{}</b> for {} [{}/{}]. Still watching?",
+    escape_html(title),
+    parse_duration(idle),
+    progress,
+    episode_count.map_or(String::from("?"), |count| count.to_string())
+  );
+  let status = |text: &str, status: EntryStatus| {
+    Button::new(
+      String::from(text),
+      QueryCommand::SetStatus {
+        kitsu_id,
+        anime_id: anime_id.to_owned(),
+        entry_id: entry_id.to_owned(),
+        status,
+      },
+    )
+  };
+  let buttons = vec![
+    vec![
+      Button::new(
+        String::from("Continue"),
+        QueryCommand::Detail {
+          kitsu_id,
+          anime_id: anime_id.to_owned(),
+        },
+      ),
+      status("Put on hold", EntryStatus::OnHold),
+      status("Drop", EntryStatus::Dropped),
+    ],
+  ];
+  (text, buttons)
+}
+
+pub fn parse_digest(settings: &Settings, digest: &Digest) -> String {
+  let mut text = format!(
+    "<b>Your {} digest</b>\n",
+    match settings.digest_frequency {
+      DigestFrequency::Daily => "daily",
+      DigestFrequency::Weekly => "weekly",
+    }
+  );
+  if !digest.new_episodes.is_empty() {
+    text.push_str("\n<b>New episodes</b>\n");
+    for &(ref title, ref episodes) in &digest.new_episodes {
+      let episodes: Vec<String> = episodes.iter().map(|episode| episode.to_string()).collect();
+      text.push_str(&format!(
+        "- {}: episode {}\n",
+        escape_html(title),
+        episodes.join(", ")
+      ));
+    }
+  }
+  if !digest.stale.is_empty() {
+    text.push_str("\n<b>Not touched in a while</b>\n");
+    for &(ref title, idle) in &digest.stale {
+      text.push_str(&format!("- {}, {} ago\n", escape_html(title), parse_duration(idle)));
     }
   }
-  text.push_str("<i>Choose an anime from the list above.</i>");
-  (text, vec![index, navigate])
+  text.push_str(&format!(
+    "\n<b>This week</b>\n{} episode(s) of {} show(s) watched.",
+    digest.watched_episodes,
+    digest.watched_shows
+  ));
+  text
+}
+
+pub fn parse_accounts(active: Option<i64>, users: Vec<User>) -> String {
+  let mut text = String::from("<b>Linked Kitsu Accounts</b>\n");
+  for user in users {
+    let is_active = active.map_or(false, |id| id.to_string() == user.id);
+    text.push_str(&format!(
+      "{} {} (#{})\n",
+      if is_active { "*" } else { "-" },
+      escape_html(&user.attributes.name),
+      user.id
+    ));
+  }
+  text.push_str("<i>Use /use &lt;name&gt; to switch accounts.</i>");
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::from_str;
+
+  use super::parse_anime_detail;
+  use settings::{RatingScale, Settings};
+  use templates::Templates;
+  use types::kitsu::{Anime, Entry, TitleLanguagePreference};
+
+  fn pair() -> Option<(Entry, Anime)> {
+    let entry = from_str(
+      r#"{
+        "id": "10",
+        "type": "libraryEntries",
+        "attributes": {
+          "status": "current",
+          "progress": 3,
+          "ratingTwenty": 16,
+          "updatedAt": "2018-01-01T00:00:00.000Z"
+        }
+      }"#,
+    ).unwrap();
+    let anime = from_str(
+      r#"{
+        "id": "100",
+        "attributes": {
+          "canonicalTitle": "Eromanga Sensei",
+          "titles": { "ja_jp": "エロマンガ先生" },
+          "episodeCount": 12,
+          "status": "finished",
+          "subtype": "TV",
+          "averageRating": "82.40",
+          "startDate": "2017-04-09",
+          "endDate": "2017-06-24"
+        }
+      }"#,
+    ).unwrap();
+    Some((entry, anime))
+  }
+
+  fn detail(settings: &Settings) -> String {
+    let genres = vec![String::from("Comedy"), String::from("Romance")];
+    let templates = Templates::load(None);
+    let preference = TitleLanguagePreference::Canonical;
+    parse_anime_detail(1, &preference, settings, &templates, pair(), genres).1
+  }
+
+  #[test]
+  fn detail_with_default_settings() {
+    let text = detail(&Settings::default());
+    assert!(text.starts_with(
+      "<b>Eromanga Sensei</b> <i>エロマンガ先生</i>\n\
+       <b>Subtype</b>: TV <b>Status</b>: Finished\n\
+       <b>Rating</b>: 82.40% <b>Age Rating</b>: null\n\
+       <b>Genres</b>: Comedy, Romance\n\
+       <b>Aired</b>: 2017-04-09 ~ 2017-06-24\n\
+       <b>Progress</b>: Current [3/12]\n\
+       <b>Rewatched</b>: 0 times\n\
+       <b>Updated</b>: "
+    ));
+    // only how long ago, and no own rating without a picked scale
+    assert!(text.ends_with(" ago"));
+    assert!(!text.contains("2018-01-01"));
+    assert!(!text.contains("Your Rating"));
+  }
+
+  #[test]
+  fn detail_with_picked_timezone_and_rating_scale() {
+    let mut settings = Settings::default();
+    settings.timezone = Some(String::from("Asia/Tokyo"));
+    settings.rating_scale = Some(RatingScale::Ten);
+    let text = detail(&settings);
+    assert!(text.contains("<b>Rating</b>: 8.2/10 "));
+    assert!(text.contains("<b>Updated</b>: 2018-01-01 09:00 ("));
+    assert!(text.ends_with("\n<b>Your Rating</b>: 8.0/10"));
+  }
 }